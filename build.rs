@@ -0,0 +1,46 @@
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+// The Rust programs of `src/bin` are linked for the userspace with their own
+// script and included in the kernel by the installer, so they are built here
+// by a nested invocation of cargo in a separate target directory.
+const PROGRAMS: [&str; 3] = ["args", "count", "heap"];
+
+fn main() {
+    println!("cargo:rerun-if-changed=src");
+    println!("cargo:rerun-if-changed=linker.ld");
+
+    // The programs are built with the `userspace` feature, and they don't
+    // include themselves.
+    if env::var_os("CARGO_FEATURE_USERSPACE").is_some() {
+        return;
+    }
+
+    let manifest_dir = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap());
+    let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+    let target_dir = out_dir.join("target");
+    let bin_dir = out_dir.join("bin");
+    fs::create_dir_all(&bin_dir).unwrap();
+
+    let cargo = env::var("CARGO").unwrap_or_else(|_| "cargo".into());
+    for name in PROGRAMS.iter() {
+        let status = Command::new(&cargo)
+            .current_dir(&manifest_dir)
+            .env_remove("CARGO_ENCODED_RUSTFLAGS") // Use the flags of `.cargo/config.toml`
+            .args(&["rustc", "--no-default-features", "--features", "userspace", "--release"])
+            .args(&["--bin", name, "--target-dir"]).arg(&target_dir)
+            // NOTE: The code is left position independent because the user
+            // space is too high for the absolute addresses of the small code
+            // model, but it is linked at the address given by the script.
+            .args(&["--", "-C", "link-arg=-Tlinker.ld", "-C", "link-arg=--strip-all"])
+            .status()
+            .expect("could not run cargo");
+        if !status.success() {
+            panic!("could not build user program '{}'", name);
+        }
+        let program = target_dir.join("x86_64-moros").join("release").join(name);
+        fs::copy(&program, bin_dir.join(name)).unwrap();
+    }
+}
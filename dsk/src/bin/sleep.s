@@ -4,5 +4,5 @@ _start:
   mov rdi, __float64__(1.0) ; time to sleep in seconds
   mov rsi, 0
   mov rdx, 0
-  int 0x80
+  syscall
   jmp _start
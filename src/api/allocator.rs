@@ -0,0 +1,52 @@
+use crate::api::syscall;
+use core::alloc::{GlobalAlloc, Layout};
+use core::ptr::{self, NonNull};
+use linked_list_allocator::Heap;
+use spin::Mutex;
+
+// Minimum size requested to the kernel when the heap needs to grow
+const HEAP_GROWTH: usize = 64 << 10; // KB
+const PAGE_SIZE: usize = 4096;
+
+#[global_allocator]
+static ALLOCATOR: UserAllocator = UserAllocator::new();
+
+// The heap of a user program starts empty and grows with the memory given by
+// the ALLOC syscall, which is always contiguous with the previous allocation.
+pub struct UserAllocator {
+    heap: Mutex<Heap>,
+}
+
+impl UserAllocator {
+    pub const fn new() -> Self {
+        Self { heap: Mutex::new(Heap::empty()) }
+    }
+}
+
+unsafe impl GlobalAlloc for UserAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let mut heap = self.heap.lock();
+        if let Ok(ptr) = heap.allocate_first_fit(layout) {
+            return ptr.as_ptr();
+        }
+        // The kernel gives whole pages so the heap must grow by whole pages
+        // to stay contiguous.
+        let size = (layout.size() + layout.align()).max(HEAP_GROWTH);
+        let size = (size + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
+        match syscall::alloc(size) {
+            Ok(addr) => {
+                if heap.size() == 0 {
+                    heap.init(addr, size);
+                } else {
+                    heap.extend(size);
+                }
+                heap.allocate_first_fit(layout).map_or(ptr::null_mut(), |ptr| ptr.as_ptr())
+            }
+            Err(_) => ptr::null_mut(),
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.heap.lock().deallocate(NonNull::new_unchecked(ptr), layout);
+    }
+}
@@ -1,6 +1,6 @@
-use crate::api::syscall;
-use crate::sys::fs::{OpenFlag, DeviceType};
-use crate::sys;
+use crate::api::{process, syscall};
+use crate::api::syscall::Error;
+use crate::sys::fs::{OpenFlag, DeviceType, FileInfo, SeekFrom};
 
 use alloc::format;
 use alloc::string::{String, ToString};
@@ -36,14 +36,14 @@ pub fn realpath(pathname: &str) -> String {
     if pathname.starts_with('/') {
         pathname.into()
     } else {
-        let dirname = sys::process::dir();
+        let dirname = current_dir();
         let sep = if dirname.ends_with('/') { "" } else { "/" };
         format!("{}{}{}", dirname, sep, pathname)
     }
 }
 
 pub fn canonicalize(path: &str) -> Result<String, ()> {
-    match sys::process::env("HOME") {
+    match process::env("HOME") {
         Some(home) => {
             if path.starts_with('~') {
                 Ok(path.replace("~", &home))
@@ -57,44 +57,95 @@ pub fn canonicalize(path: &str) -> Result<String, ()> {
     }
 }
 
+pub fn current_dir() -> String {
+    let buf = read_with(syscall::getcwd).unwrap_or_default();
+    String::from_utf8_lossy(&buf).to_string()
+}
+
+pub fn change_dir(path: &str) -> Result<(), ()> {
+    syscall::chdir(path).map_err(|_| ())
+}
+
+// Call a syscall copying data into a buffer until the buffer is big enough
+pub(crate) fn read_with(f: impl Fn(&mut [u8]) -> Result<usize, Error>) -> Result<Vec<u8>, Error> {
+    let mut buf = vec![0; 256];
+    loop {
+        let n = f(&mut buf)?;
+        if n <= buf.len() {
+            buf.truncate(n);
+            return Ok(buf);
+        }
+        buf.resize(n, 0);
+    }
+}
+
 pub fn exists(path: &str) -> bool {
-    syscall::stat(path).is_some()
+    syscall::stat(path).is_ok()
 }
 
 pub fn open_file(path: &str) -> Option<usize> {
     let flags = 0;
-    syscall::open(path, flags)
+    syscall::open(path, flags).ok()
 }
 
 pub fn create_file(path: &str) -> Option<usize> {
     let flags = OpenFlag::Create as usize;
-    syscall::open(path, flags)
+    syscall::open(path, flags).ok()
 }
 
 pub fn open_dir(path: &str) -> Option<usize> {
     let flags = OpenFlag::Dir as usize;
-    syscall::open(path, flags)
+    syscall::open(path, flags).ok()
 }
 
 pub fn create_dir(path: &str) -> Option<usize> {
     let flags = OpenFlag::Create as usize | OpenFlag::Dir as usize;
-    syscall::open(path, flags)
+    syscall::open(path, flags).ok()
 }
 
 pub fn open_device(path: &str) -> Option<usize> {
     let flags = OpenFlag::Device as usize;
-    syscall::open(path, flags)
+    syscall::open(path, flags).ok()
 }
 
 pub fn create_device(path: &str, kind: DeviceType) -> Option<usize> {
     let flags = OpenFlag::Create as usize | OpenFlag::Device as usize;
-    if let Some(handle) = syscall::open(path, flags) {
+    if let Ok(handle) = syscall::open(path, flags) {
         let buf = [kind as u8; 1];
-        return syscall::write(handle, &buf);
+        return syscall::write(handle, &buf).ok();
     }
     None
 }
 
+pub fn seek(handle: usize, pos: SeekFrom) -> Result<usize, ()> {
+    let (offset, whence) = match pos {
+        SeekFrom::Start(i)   => (i as isize, 0),
+        SeekFrom::Current(i) => (i as isize, 1),
+        SeekFrom::End(i)     => (i as isize, 2),
+    };
+    syscall::seek(handle, offset, whence).map_err(|_| ())
+}
+
+// Delete a file or an empty directory
+pub fn delete(path: &str) -> Result<(), ()> {
+    syscall::unlink(path).map_err(|_| ())
+}
+
+pub fn rename(from: &str, to: &str) -> Result<(), ()> {
+    syscall::rename(from, to).map_err(|_| ())
+}
+
+pub fn read_dir(path: &str) -> Result<Vec<FileInfo>, ()> {
+    let buf = read_with(|buf| syscall::readdir(path, buf)).map_err(|_| ())?;
+    let mut files = Vec::new();
+    let mut i = 0;
+    while let Some(file) = FileInfo::from_bytes(&buf[i..]) {
+        i += file.len();
+        files.push(file);
+    }
+    Ok(files)
+}
+
 pub fn read_to_string(path: &str) -> Result<String, ()> {
     let buf = read(path)?;
     Ok(String::from_utf8_lossy(&buf).to_string())
@@ -105,11 +156,11 @@ pub fn read(path: &str) -> Result<Vec<u8>, ()> {
         Ok(path) => path,
         Err(_) => return Err(()),
     };
-    if let Some(stat) = syscall::stat(&path) {
+    if let Ok(stat) = syscall::stat(&path) {
         let res = if stat.is_device() { open_device(&path) } else { open_file(&path) };
         if let Some(handle) = res {
             let mut buf = vec![0; stat.size() as usize];
-            if let Some(bytes) = syscall::read(handle, &mut buf) {
+            if let Ok(bytes) = syscall::read(handle, &mut buf) {
                 buf.resize(bytes, 0);
                 syscall::close(handle);
                 return Ok(buf)
@@ -125,7 +176,7 @@ pub fn write(path: &str, buf: &[u8]) -> Result<usize, ()> {
         Err(_) => return Err(()),
     };
     if let Some(handle) = create_file(&path) {
-        if let Some(bytes) = syscall::write(handle, buf) {
+        if let Ok(bytes) = syscall::write(handle, buf) {
             syscall::close(handle);
             return Ok(bytes)
         }
@@ -150,3 +201,33 @@ fn test_file() {
 
     dismount();
 }
+
+#[test_case]
+fn test_dir() {
+    use crate::sys::fs::{mount_mem, format_mem, dismount};
+    mount_mem();
+    format_mem();
+
+    let handle = create_dir("/tmp").unwrap();
+    syscall::close(handle);
+    assert_eq!(write("/tmp/a", b"A"), Ok(1));
+
+    // Rename file
+    assert_eq!(rename("/tmp/a", "/tmp/b"), Ok(()));
+    assert!(!exists("/tmp/a"));
+    assert_eq!(read("/tmp/b"), Ok(b"A".to_vec()));
+
+    // Read dir
+    let files = read_dir("/tmp").unwrap();
+    assert_eq!(files.len(), 1);
+    assert_eq!(files[0].name(), "b");
+    assert_eq!(files[0].size(), 1);
+
+    // Delete dir
+    assert_eq!(delete("/tmp"), Err(()));
+    assert_eq!(delete("/tmp/b"), Ok(()));
+    assert_eq!(delete("/tmp"), Ok(()));
+    assert!(!exists("/tmp"));
+
+    dismount();
+}
@@ -13,7 +13,7 @@ impl Stdin {
 
     pub fn read_char(&self) -> Option<char> {
         let mut buf = vec![0; 1];
-        if let Some(bytes) = syscall::read(0, &mut buf) {
+        if let Ok(bytes) = syscall::read(0, &mut buf) {
             if bytes > 0 {
                 return Some(buf[0] as char);
             }
@@ -23,7 +23,7 @@ impl Stdin {
 
     pub fn read_line(&self) -> String {
         let mut buf = vec![0; 256];
-        if let Some(bytes) = syscall::read(0, &mut buf) {
+        if let Ok(bytes) = syscall::read(0, &mut buf) {
             buf.resize(bytes, 0);
             String::from_utf8_lossy(&buf).to_string()
         } else {
@@ -38,7 +38,7 @@ impl Stdout {
     }
 
     pub fn write(&self, s: &str) {
-        syscall::write(1, s.as_bytes());
+        syscall::write(1, s.as_bytes()).ok();
     }
 }
 
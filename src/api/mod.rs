@@ -17,6 +17,48 @@ macro_rules! println {
     });
 }
 
+// Define the entry point of a user program with a function taking its
+// arguments and returning an exit code, like the commands of the shell.
+// NOTE: The program must enable the `asm` and `naked_functions` features.
+#[macro_export]
+macro_rules! entry_point {
+    ($path:path) => {
+        #[panic_handler]
+        fn panic(info: &core::panic::PanicInfo) -> ! {
+            $crate::println!("{}", info);
+            $crate::api::syscall::exit($crate::usr::shell::ExitCode::CommandError as usize);
+            loop {}
+        }
+
+        // The program is entered with the stack aligned on 16 bytes, while a
+        // function expects it to be offset by the return address pushed by
+        // its call, so the main function is called from assembly with the
+        // arguments left in `rdi` and `rsi`.
+        #[export_name = "_start"]
+        #[naked]
+        pub unsafe extern "sysv64" fn __impl_start() -> ! {
+            asm!(
+                "xor rbp, rbp", // Last frame of the backtrace
+                "and rsp, -16",
+                "call {}",
+                "ud2",
+                sym __impl_main,
+                options(noreturn)
+            );
+        }
+
+        unsafe extern "sysv64" fn __impl_main(argc: usize, argv: *const *const u8) -> ! {
+            let args = $crate::api::process::args_from_raw(argc, argv);
+            let f: fn(&[&str]) -> $crate::usr::shell::ExitCode = $path;
+            let code = f(&args);
+            $crate::api::syscall::exit(code as usize);
+            loop {}
+        }
+    };
+}
+
+#[cfg(feature = "userspace")]
+pub mod allocator;
 pub mod console;
 pub mod font;
 pub mod fs;
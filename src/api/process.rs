@@ -1,12 +1,70 @@
 use crate::api::syscall;
 use crate::api::fs;
+use crate::sys::process::{ProcessInfo, MAX_PROCS};
+use crate::sys::syscall::number::SIGRETURN;
+pub use crate::sys::process::SIGINT;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
 
-pub fn spawn(path: &str) -> Result<(), ()> {
+// Start a program in a new process and return its PID
+pub fn spawn(path: &str, args: &[&str]) -> Result<usize, ()> {
     if let Ok(path) = fs::canonicalize(path) {
-        if syscall::stat(&path).is_some() {
-            syscall::spawn(&path);
-            return Ok(());
+        if syscall::stat(&path).is_ok() {
+            return syscall::spawn(&path, args).map_err(|_| ());
         }
     }
     Err(())
 }
+
+// Block until the given child process has exited and return its exit code
+pub fn wait(id: usize) -> Result<usize, ()> {
+    syscall::wait(id).map_err(|_| ())
+}
+
+// Collect the arguments given by the kernel to a user program on its stack
+pub unsafe fn args_from_raw(argc: usize, argv: *const *const u8) -> Vec<&'static str> {
+    (0..argc).map(|i| {
+        let ptr = *argv.add(i);
+        let mut len = 0;
+        while *ptr.add(len) != 0 {
+            len += 1;
+        }
+        core::str::from_utf8_unchecked(core::slice::from_raw_parts(ptr, len))
+    }).collect()
+}
+
+pub fn env(key: &str) -> Option<String> {
+    let buf = fs::read_with(|buf| syscall::getenv(key, buf)).ok()?;
+    Some(String::from_utf8_lossy(&buf).to_string())
+}
+
+pub fn set_env(key: &str, val: &str) -> Result<(), ()> {
+    syscall::setenv(key, val).map_err(|_| ())
+}
+
+pub fn list() -> Vec<ProcessInfo> {
+    let mut procs = vec![ProcessInfo::new(); MAX_PROCS];
+    let n = syscall::procs(&mut procs).unwrap_or(0);
+    procs.truncate(n);
+    procs
+}
+
+pub fn kill(id: usize) -> Result<(), ()> {
+    syscall::kill(id).map_err(|_| ())
+}
+
+// Call the given function when the current process receives a signal, like
+// `SIGINT` when Ctrl-C is pressed, instead of terminating it.
+pub fn signal(signal: usize, handler: extern "sysv64" fn(usize)) -> Result<(), ()> {
+    syscall::signal(signal, handler as usize, sigreturn as usize).map_err(|_| ())
+}
+
+// Address where a signal handler returns to resume the interrupted context
+#[naked]
+unsafe extern "sysv64" fn sigreturn() {
+    #[cfg(feature = "userspace")]
+    asm!("mov rax, {}", "syscall", const SIGRETURN, options(noreturn));
+    #[cfg(not(feature = "userspace"))]
+    asm!("mov rax, {}", "int 0x80", const SIGRETURN, options(noreturn));
+}
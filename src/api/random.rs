@@ -4,7 +4,7 @@ use crate::api::fs;
 pub fn get_u64() -> u64 {
     let mut buf = [0; 8];
     if let Some(handle) = fs::open_device("/dev/random") {
-        if syscall::read(handle, &mut buf).is_some() {
+        if syscall::read(handle, &mut buf).is_ok() {
             return u64::from_be_bytes(buf);
         }
     }
@@ -14,7 +14,7 @@ pub fn get_u64() -> u64 {
 pub fn get_u16() -> u16 {
     let mut buf = [0; 2];
     if let Some(handle) = fs::open_device("/dev/random") {
-        if syscall::read(handle, &mut buf).is_some() {
+        if syscall::read(handle, &mut buf).is_ok() {
             return u16::from_be_bytes(buf);
         }
     }
@@ -1,6 +1,62 @@
 use crate::syscall;
 use crate::sys::syscall::number::*;
 use crate::sys::fs::FileStat;
+use crate::sys::process::ProcessInfo;
+
+// Errors returned by system calls as negative numbers, with the same values
+// as the errno codes of Linux.
+#[allow(clippy::upper_case_acronyms)]
+#[repr(isize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    EPERM     = -1,  // Operation not permitted
+    ENOENT    = -2,  // No such file or directory
+    ESRCH     = -3,  // No such process
+    EIO       = -5,  // Input/output error
+    ENOEXEC   = -8,  // Exec format error
+    EBADF     = -9,  // Bad file handle
+    ECHILD    = -10, // No child process
+    ENOMEM    = -12, // Out of memory
+    EFAULT    = -14, // Bad address
+    EEXIST    = -17, // File exists
+    EINVAL    = -22, // Invalid argument
+    EMFILE    = -24, // Too many open files
+    ESPIPE    = -29, // Illegal seek
+    ENOSYS    = -38, // Function not implemented
+    ENOTEMPTY = -39, // Directory not empty
+}
+
+impl From<isize> for Error {
+    fn from(code: isize) -> Self {
+        match code {
+            -1  => Error::EPERM,
+            -2  => Error::ENOENT,
+            -3  => Error::ESRCH,
+            -5  => Error::EIO,
+            -8  => Error::ENOEXEC,
+            -9  => Error::EBADF,
+            -10 => Error::ECHILD,
+            -12 => Error::ENOMEM,
+            -14 => Error::EFAULT,
+            -17 => Error::EEXIST,
+            -22 => Error::EINVAL,
+            -24 => Error::EMFILE,
+            -29 => Error::ESPIPE,
+            -38 => Error::ENOSYS,
+            -39 => Error::ENOTEMPTY,
+            _   => Error::EIO,
+        }
+    }
+}
+
+// Convert the value returned by a system call into a result
+fn to_result(res: isize) -> Result<usize, Error> {
+    if res.is_negative() {
+        Err(Error::from(res))
+    } else {
+        Ok(res as usize)
+    }
+}
 
 pub fn sleep(seconds: f64) {
     unsafe { syscall!(SLEEP, seconds.to_bits()) };
@@ -16,60 +72,184 @@ pub fn realtime() -> f64 {
     f64::from_bits(res as u64)
 }
 
-pub fn stat(path: &str) -> Option<FileStat> {
+// Nanoseconds since boot
+pub fn monotonic() -> u64 {
+    unsafe { syscall!(MONOTONIC) as u64 }
+}
+
+// Shift the realtime clock by the given number of seconds
+pub fn adjtime(seconds: f64) {
+    unsafe { syscall!(ADJTIME, seconds.to_bits()) };
+}
+
+pub fn stat(path: &str) -> Result<FileStat, Error> {
     let path_ptr = path.as_ptr() as usize;
     let path_len = path.len() as usize;
     let mut stat = FileStat::new();
     let stat_ptr = &mut stat as *mut FileStat as usize;
     let res = unsafe { syscall!(STAT, path_ptr, path_len, stat_ptr) } as isize;
-    if res.is_negative() {
-        None
-    } else {
-        Some(stat)
-    }
+    to_result(res).map(|_| stat)
 }
 
-pub fn open(path: &str, flags: usize) -> Option<usize> {
+pub fn open(path: &str, flags: usize) -> Result<usize, Error> {
     let ptr = path.as_ptr() as usize;
     let len = path.len() as usize;
     let res = unsafe { syscall!(OPEN, ptr, len, flags) } as isize;
-    if res.is_negative() {
-        None
-    } else {
-        Some(res as usize)
-    }
+    to_result(res)
 }
 
-pub fn read(handle: usize, buf: &mut [u8]) -> Option<usize> {
+pub fn read(handle: usize, buf: &mut [u8]) -> Result<usize, Error> {
     let ptr = buf.as_ptr() as usize;
     let len = buf.len() as usize;
     let res = unsafe { syscall!(READ, handle, ptr, len) } as isize;
-    if res.is_negative() {
-        None
-    } else {
-        Some(res as usize)
-    }
+    to_result(res)
 }
 
-pub fn write(handle: usize, buf: &[u8]) -> Option<usize> {
+pub fn write(handle: usize, buf: &[u8]) -> Result<usize, Error> {
     let ptr = buf.as_ptr() as usize;
     let len = buf.len() as usize;
     let res = unsafe { syscall!(WRITE, handle, ptr, len) } as isize;
-    if res.is_negative() {
-        None
-    } else {
-        Some(res as usize)
-    }
+    to_result(res)
 }
 
-pub fn close(handle: usize) {
-    unsafe { syscall!(CLOSE, handle as usize) };
+pub fn seek(handle: usize, offset: isize, whence: usize) -> Result<usize, Error> {
+    let res = unsafe { syscall!(SEEK, handle, offset, whence) } as isize;
+    to_result(res)
+}
+
+pub fn unlink(path: &str) -> Result<(), Error> {
+    let ptr = path.as_ptr() as usize;
+    let len = path.len() as usize;
+    let res = unsafe { syscall!(UNLINK, ptr, len) } as isize;
+    to_result(res).map(|_| ())
+}
+
+pub fn rename(from: &str, to: &str) -> Result<(), Error> {
+    let from_ptr = from.as_ptr() as usize;
+    let from_len = from.len() as usize;
+    let to_ptr = to.as_ptr() as usize;
+    let to_len = to.len() as usize;
+    let res = unsafe { syscall!(RENAME, from_ptr, from_len, to_ptr, to_len) } as isize;
+    to_result(res).map(|_| ())
+}
+
+// Read the serialized entries of a directory and return their total size,
+// that can be bigger than the buffer.
+pub fn readdir(path: &str, buf: &mut [u8]) -> Result<usize, Error> {
+    let path_ptr = path.as_ptr() as usize;
+    let path_len = path.len() as usize;
+    let buf_ptr = buf.as_mut_ptr() as usize;
+    let buf_len = buf.len() as usize;
+    let res = unsafe { syscall!(READDIR, path_ptr, path_len, buf_ptr, buf_len) } as isize;
+    to_result(res)
 }
 
-pub fn spawn(path: &str) {
+pub fn chdir(path: &str) -> Result<(), Error> {
     let ptr = path.as_ptr() as usize;
     let len = path.len() as usize;
-    unsafe { syscall!(SPAWN, ptr, len) };
+    let res = unsafe { syscall!(CHDIR, ptr, len) } as isize;
+    to_result(res).map(|_| ())
+}
+
+// Copy the current directory into the buffer and return its length, that can
+// be bigger than the buffer.
+pub fn getcwd(buf: &mut [u8]) -> Result<usize, Error> {
+    let ptr = buf.as_mut_ptr() as usize;
+    let len = buf.len() as usize;
+    let res = unsafe { syscall!(GETCWD, ptr, len) } as isize;
+    to_result(res)
+}
+
+// Copy the value of an environment variable into the buffer and return its
+// length, that can be bigger than the buffer.
+pub fn getenv(key: &str, buf: &mut [u8]) -> Result<usize, Error> {
+    let key_ptr = key.as_ptr() as usize;
+    let key_len = key.len() as usize;
+    let buf_ptr = buf.as_mut_ptr() as usize;
+    let buf_len = buf.len() as usize;
+    let res = unsafe { syscall!(GETENV, key_ptr, key_len, buf_ptr, buf_len) } as isize;
+    to_result(res)
+}
+
+pub fn setenv(key: &str, val: &str) -> Result<(), Error> {
+    let key_ptr = key.as_ptr() as usize;
+    let key_len = key.len() as usize;
+    let val_ptr = val.as_ptr() as usize;
+    let val_len = val.len() as usize;
+    let res = unsafe { syscall!(SETENV, key_ptr, key_len, val_ptr, val_len) } as isize;
+    to_result(res).map(|_| ())
+}
+
+// Copy a file handle to the lowest free handle and return it
+pub fn dup(handle: usize) -> Result<usize, Error> {
+    let res = unsafe { syscall!(DUP, handle) } as isize;
+    to_result(res)
+}
+
+// Make a file handle refer to the same resource as another one
+pub fn dup2(handle: usize, new_handle: usize) -> Result<usize, Error> {
+    let res = unsafe { syscall!(DUP2, handle, new_handle) } as isize;
+    to_result(res)
+}
+
+// Create a pipe and return the handles of its read end and its write end
+pub fn pipe() -> Result<(usize, usize), Error> {
+    let mut handles = [0usize; 2];
+    let ptr = handles.as_mut_ptr() as usize;
+    let res = unsafe { syscall!(PIPE, ptr) } as isize;
+    to_result(res).map(|_| (handles[0], handles[1]))
+}
+
+// Register a signal handler that will return to the given restorer
+pub fn signal(signal: usize, handler: usize, restorer: usize) -> Result<(), Error> {
+    let res = unsafe { syscall!(SIGNAL, signal, handler, restorer) } as isize;
+    to_result(res).map(|_| ())
+}
+
+pub fn close(handle: usize) {
+    unsafe { syscall!(CLOSE, handle as usize) };
+}
+
+pub fn exit(code: usize) {
+    unsafe { syscall!(EXIT, code) };
+}
+
+pub fn procs(buf: &mut [ProcessInfo]) -> Result<usize, Error> {
+    let ptr = buf.as_mut_ptr() as usize;
+    let len = buf.len() as usize;
+    let res = unsafe { syscall!(PROCS, ptr, len) } as isize;
+    to_result(res)
+}
+
+pub fn wait(id: usize) -> Result<usize, Error> {
+    let res = unsafe { syscall!(WAIT, id) } as isize;
+    to_result(res)
+}
+
+// Extend the heap of the process and return the address of the new memory
+pub fn alloc(size: usize) -> Result<usize, Error> {
+    let res = unsafe { syscall!(ALLOC, size) } as isize;
+    to_result(res)
+}
+
+// Release memory given by `alloc` back to the system
+pub fn free(addr: usize, size: usize) -> Result<(), Error> {
+    let res = unsafe { syscall!(FREE, addr, size) } as isize;
+    to_result(res).map(|_| ())
+}
+
+pub fn kill(id: usize) -> Result<(), Error> {
+    let res = unsafe { syscall!(KILL, id) } as isize;
+    to_result(res).map(|_| ())
+}
+
+pub fn spawn(path: &str, args: &[&str]) -> Result<usize, Error> {
+    let path_ptr = path.as_ptr() as usize;
+    let path_len = path.len() as usize;
+    let args_ptr = args.as_ptr() as usize;
+    let args_len = args.len() as usize;
+    let res = unsafe { syscall!(SPAWN, path_ptr, path_len, args_ptr, args_len) } as isize;
+    to_result(res)
 }
 
 #[test_case]
@@ -80,29 +260,29 @@ fn test_file() {
     format_mem();
 
     let flags = 0;
-    assert_eq!(open("/test", flags), None);
+    assert_eq!(open("/test", flags), Err(Error::ENOENT));
 
     // Write file
     let flags = OpenFlag::Create as usize;
-    assert_eq!(open("/test", flags), Some(4));
+    assert_eq!(open("/test", flags), Ok(4));
     let input = "Hello, world!".as_bytes();
-    assert_eq!(write(4, &input), Some(input.len()));
+    assert_eq!(write(4, &input), Ok(input.len()));
 
     // Read file
     let flags = 0;
-    assert_eq!(open("/test", flags), Some(5));
+    assert_eq!(open("/test", flags), Ok(5));
     let mut output = vec![0; input.len()];
-    assert_eq!(read(5, &mut output), Some(input.len()));
+    assert_eq!(read(5, &mut output), Ok(input.len()));
     assert_eq!(output, input);
 
     close(4);
     close(5);
 
-    assert_eq!(open("/test", flags), Some(4));
+    assert_eq!(open("/test", flags), Ok(4));
 
     close(4);
 
-    //assert!(write(1, b"Hello, World\n").is_some());
+    //assert!(write(1, b"Hello, World\n").is_ok());
 
     dismount();
 }
@@ -0,0 +1,18 @@
+#![no_std]
+#![no_main]
+#![feature(asm)]
+#![feature(naked_functions)]
+
+extern crate alloc;
+
+use moros::{entry_point, print, println};
+use moros::usr::shell::ExitCode;
+
+entry_point!(main);
+
+fn main(args: &[&str]) -> ExitCode {
+    for (i, arg) in args.iter().enumerate() {
+        println!("args[{}] = {:?}", i, arg);
+    }
+    ExitCode::CommandSuccessful
+}
@@ -0,0 +1,35 @@
+#![no_std]
+#![no_main]
+#![feature(asm)]
+#![feature(naked_functions)]
+
+extern crate alloc;
+
+use alloc::vec;
+use moros::api::{fs, syscall};
+use moros::{entry_point, print, println};
+use moros::usr::shell::ExitCode;
+
+entry_point!(main);
+
+fn main(args: &[&str]) -> ExitCode {
+    if args.len() != 2 {
+        println!("Usage: count <file>");
+        return ExitCode::CommandError;
+    }
+    let path = args[1];
+    if let Ok(stat) = syscall::stat(path) {
+        if let Some(handle) = fs::open_file(path) {
+            let mut buf = vec![0; stat.size() as usize];
+            let bytes = syscall::read(handle, &mut buf).unwrap_or(0);
+            syscall::close(handle);
+            let text = alloc::string::String::from_utf8_lossy(&buf[..bytes]);
+            let lines = text.lines().count();
+            let words = text.split_whitespace().count();
+            println!("{} lines, {} words, {} bytes", lines, words, bytes);
+            return ExitCode::CommandSuccessful;
+        }
+    }
+    println!("File not found '{}'", path);
+    ExitCode::CommandError
+}
@@ -0,0 +1,27 @@
+#![no_std]
+#![no_main]
+#![feature(asm)]
+#![feature(naked_functions)]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use moros::{entry_point, print, println};
+use moros::usr::shell::ExitCode;
+
+entry_point!(main);
+
+fn main(args: &[&str]) -> ExitCode {
+    let n = match args.get(1).map(|arg| arg.parse::<u64>()) {
+        None => 100_000,
+        Some(Ok(n)) => n,
+        Some(Err(_)) => {
+            println!("Usage: heap [<count>]");
+            return ExitCode::CommandError;
+        }
+    };
+    let vec: Vec<u64> = (0..n).collect();
+    let sum: u64 = vec.iter().sum();
+    println!("Sum of the first {} integers: {}", n, sum);
+    ExitCode::CommandSuccessful
+}
@@ -3,6 +3,7 @@
 #![feature(abi_x86_interrupt)]
 #![feature(alloc_error_handler)]
 #![feature(asm)]
+#![feature(global_asm)]
 #![feature(naked_functions)]
 #![feature(custom_test_frameworks)]
 #![test_runner(crate::test_runner)]
@@ -22,6 +23,7 @@ pub fn init(boot_info: &'static BootInfo) {
     sys::vga::init();
     sys::gdt::init();
     sys::idt::init();
+    sys::fpu::init();
     sys::pic::init(); // Enable interrupts
     sys::serial::init();
     sys::keyboard::init();
@@ -29,7 +31,10 @@ pub fn init(boot_info: &'static BootInfo) {
 
     log!("MOROS v{}\n", env!("CARGO_PKG_VERSION"));
     sys::mem::init(boot_info);
+    sys::time::calibrate(); // Require MEM
     sys::cpu::init();
+    sys::apic::init(); // Require MEM
+    sys::smp::init(); // Require APIC
     sys::pci::init(); // Require MEM
     sys::net::init(); // Require PCI
     sys::ata::init();
@@ -87,14 +92,11 @@ pub fn hlt_loop() -> ! {
     }
 }
 
-#[cfg(test)]
-use bootloader::entry_point;
-
 #[cfg(test)]
 use core::panic::PanicInfo;
 
 #[cfg(test)]
-entry_point!(test_kernel_main);
+bootloader::entry_point!(test_kernel_main);
 
 #[cfg(test)]
 fn test_kernel_main(boot_info: &'static BootInfo) -> ! {
@@ -109,7 +111,7 @@ fn panic(info: &PanicInfo) -> ! {
     let csi_color = api::console::Style::color("LightRed");
     let csi_reset = api::console::Style::reset();
     println!("{}failed{}\n", csi_color, csi_reset);
-    println!("{}\n", info);
+    sys::backtrace::report(info);
     exit_qemu(QemuExitCode::Failed);
     hlt_loop();
 }
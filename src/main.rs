@@ -29,7 +29,7 @@ fn main(boot_info: &'static BootInfo) -> ! {
 
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
-    println!("{}", info);
+    sys::backtrace::report(info);
     loop {
         sys::time::sleep(10.0)
     }
@@ -1,19 +1,20 @@
 use crate::sys;
 use alloc::slice::SliceIndex;
 use alloc::sync::Arc;
-use alloc::vec;
 use alloc::vec::Vec;
 use core::ops::{Index, IndexMut};
 use linked_list_allocator::LockedHeap;
-use spin::Mutex;
 use x86_64::structures::paging::mapper::MapToError;
-use x86_64::structures::paging::{FrameAllocator, Mapper, Page, PageTableFlags, Size4KiB};
+use x86_64::structures::paging::{FrameAllocator, Mapper, Page, PageTableFlags, PhysFrame, Size4KiB};
 use x86_64::VirtAddr;
 
 pub const HEAP_START: usize = 0x4444_4444_0000;
 pub const HEAP_SIZE: usize = 16 << 20; // MB
+const FRAME_SIZE: usize = 4096;
 
-#[global_allocator]
+// NOTE: User programs built with the `userspace` feature use the allocator
+// defined in `api::allocator` instead.
+#[cfg_attr(not(feature = "userspace"), global_allocator)]
 static ALLOCATOR: LockedHeap = LockedHeap::empty();
 
 pub fn init_heap(mapper: &mut impl Mapper<Size4KiB>, frame_allocator: &mut impl FrameAllocator<Size4KiB>) -> Result<(), MapToError<Size4KiB>> {
@@ -40,37 +41,51 @@ pub fn init_heap(mapper: &mut impl Mapper<Size4KiB>, frame_allocator: &mut impl
     Ok(())
 }
 
+// Buffer of physically contiguous memory for DMA
 #[derive(Clone)]
 pub struct PhysBuf {
-    buf: Arc<Mutex<Vec<u8>>>,
+    frames: Arc<PhysFrames>,
+    len: usize,
 }
 
 impl PhysBuf {
     pub fn new(len: usize) -> Self {
-        Self::from(vec![0; len])
+        let count = (len + FRAME_SIZE - 1) / FRAME_SIZE;
+        let frames = Arc::new(PhysFrames::new(count.max(1)));
+        Self { frames, len }
     }
 
-    // Realloc vec until it uses a chunk of contiguous physical memory
-    fn from(vec: Vec<u8>) -> Self {
-        let buffer_len = vec.len() - 1;
-        let memory_len = phys_addr(&vec[buffer_len]) - phys_addr(&vec[0]);
-        if buffer_len == memory_len as usize {
-            Self { buf: Arc::new(Mutex::new(vec)) }
-        } else {
-            Self::from(vec.clone()) // Clone vec and try again
-        }
+    pub fn addr(&self) -> u64 {
+        self.frames.start.start_address().as_u64()
     }
 
-    pub fn addr(&self) -> u64 {
-        phys_addr(&self.buf.lock()[0])
+    fn ptr(&self) -> *mut u8 {
+        sys::mem::phys_to_virt(self.frames.start.start_address()).as_mut_ptr()
     }
 }
 
-fn phys_addr(ptr: &u8) -> u64 {
-    let rx_ptr = ptr as *const u8;
-    let virt_addr = VirtAddr::new(rx_ptr as u64);
-    let phys_addr = sys::mem::virt_to_phys(virt_addr).unwrap();
-    phys_addr.as_u64()
+struct PhysFrames {
+    start: PhysFrame,
+    count: usize,
+}
+
+impl PhysFrames {
+    fn new(count: usize) -> Self {
+        let start = sys::mem::frame_allocator().allocate_frames(count).expect("could not allocate physical frames");
+        let ptr = sys::mem::phys_to_virt(start.start_address()).as_mut_ptr::<u8>();
+        unsafe {
+            core::ptr::write_bytes(ptr, 0, count * FRAME_SIZE);
+        }
+        Self { start, count }
+    }
+}
+
+impl Drop for PhysFrames {
+    fn drop(&mut self) {
+        for frame in PhysFrame::range(self.start, self.start + self.count as u64) {
+            sys::mem::deallocate_frame(frame);
+        }
+    }
 }
 
 impl<I: SliceIndex<[u8]>> Index<I> for PhysBuf {
@@ -93,15 +108,13 @@ impl core::ops::Deref for PhysBuf {
     type Target = [u8];
 
     fn deref(&self) -> &[u8] {
-        let vec = self.buf.lock();
-        unsafe { alloc::slice::from_raw_parts(vec.as_ptr(), vec.len()) }
+        unsafe { alloc::slice::from_raw_parts(self.ptr(), self.len) }
     }
 }
 
 impl core::ops::DerefMut for PhysBuf {
     fn deref_mut(&mut self) -> &mut [u8] {
-        let mut vec = self.buf.lock();
-        unsafe { alloc::slice::from_raw_parts_mut(vec.as_mut_ptr(), vec.len()) }
+        unsafe { alloc::slice::from_raw_parts_mut(self.ptr(), self.len) }
     }
 }
 
@@ -132,6 +145,15 @@ fn many_boxes() {
     }
 }
 
+#[test_case]
+fn phys_buf() {
+    let mut buf = PhysBuf::new(5000);
+    assert_eq!(buf.len(), 5000);
+    assert_eq!(buf.addr() % FRAME_SIZE as u64, 0);
+    buf[4999] = 42;
+    assert_eq!(buf[4999], 42);
+}
+
 #[test_case]
 fn large_vec() {
     let n = 1000;
@@ -0,0 +1,280 @@
+use crate::sys;
+use crate::sys::acpi::MorosAcpiHandler;
+use acpi::AcpiTables;
+use acpi::platform::ProcessorState;
+use acpi::platform::interrupt::{InterruptModel, Polarity, TriggerMode};
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, Ordering};
+use raw_cpuid::CpuId;
+use spin::Once;
+use x86_64::instructions::interrupts;
+
+// The Local APIC of the CPU and the IOAPIC of the chipset replace the legacy
+// PIC when they are described in the MADT of ACPI. The legacy IRQs keep the
+// same interrupt vectors but are routed to the global system interrupts (GSI)
+// of the IOAPIC, with the overrides given by the MADT, and the scheduler is
+// driven by the LAPIC timer while the PIT is only used for the clock.
+
+pub const TIMER_VECTOR: u8 = 0x30;
+pub const SPURIOUS_VECTOR: u8 = 0xFF;
+
+// Local APIC registers
+const LAPIC_ID: u64 = 0x020;
+const LAPIC_TPR: u64 = 0x080;
+const LAPIC_EOI: u64 = 0x0B0;
+const LAPIC_SVR: u64 = 0x0F0;
+const LAPIC_ICR_LOW: u64 = 0x300;
+const LAPIC_ICR_HIGH: u64 = 0x310;
+const LAPIC_LVT_TIMER: u64 = 0x320;
+const LAPIC_TIMER_INITIAL_COUNT: u64 = 0x380;
+const LAPIC_TIMER_CURRENT_COUNT: u64 = 0x390;
+const LAPIC_TIMER_DIVIDE: u64 = 0x3E0;
+
+const LAPIC_SVR_ENABLE: u32 = 1 << 8;
+const LAPIC_LVT_MASKED: u32 = 1 << 16;
+const LAPIC_LVT_PERIODIC: u32 = 1 << 17;
+const LAPIC_TIMER_DIVIDE_BY_16: u32 = 0b0011;
+const LAPIC_ICR_INIT: u32 = 0b101 << 8;
+const LAPIC_ICR_STARTUP: u32 = 0b110 << 8;
+const LAPIC_ICR_PENDING: u32 = 1 << 12;
+const LAPIC_ICR_ASSERT: u32 = 1 << 14;
+
+// IOAPIC registers
+const IOAPIC_REGSEL: u64 = 0x00;
+const IOAPIC_WINDOW: u64 = 0x10;
+const IOAPIC_VERSION: u32 = 0x01;
+const IOAPIC_REDIRECTION_TABLE: u32 = 0x10;
+
+const IOAPIC_ACTIVE_LOW: u32 = 1 << 13;
+const IOAPIC_LEVEL_TRIGGERED: u32 = 1 << 15;
+const IOAPIC_MASKED: u32 = 1 << 16;
+
+const CALIBRATION_TICKS: usize = 10;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static APIC: Once<Apic> = Once::new();
+
+#[derive(Debug, Clone, Copy)]
+struct IoApic {
+    addr: u64, // Virtual address of the registers
+    gsi_base: u32,
+    gsi_count: u32,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Route {
+    gsi: u32,
+    active_low: bool,
+    level_triggered: bool,
+}
+
+#[derive(Debug)]
+struct Apic {
+    lapic_addr: u64, // Virtual address of the registers
+    io_apics: Vec<IoApic>,
+    routes: [Route; 16], // GSI of each legacy IRQ
+    timer_count: u32, // LAPIC timer ticks between two PIT ticks
+    bsp_id: u32, // LAPIC ID of the bootstrap processor
+    ap_ids: Vec<u32>, // LAPIC IDs of the application processors
+}
+
+impl Apic {
+    fn io_apic(&self, gsi: u32) -> Option<&IoApic> {
+        self.io_apics.iter().find(|io| io.gsi_base <= gsi && gsi < io.gsi_base + io.gsi_count)
+    }
+
+    // Write the redirection entry of a legacy IRQ to deliver it to the
+    // bootstrap processor with the same vector as the PIC.
+    fn set_route(&self, irq: u8, masked: bool) {
+        let route = self.routes[irq as usize];
+        if let Some(io_apic) = self.io_apic(route.gsi) {
+            let mut low = (sys::pic::PIC_1_OFFSET + irq) as u32;
+            if route.active_low {
+                low |= IOAPIC_ACTIVE_LOW;
+            }
+            if route.level_triggered {
+                low |= IOAPIC_LEVEL_TRIGGERED;
+            }
+            if masked {
+                low |= IOAPIC_MASKED;
+            }
+            let high = self.bsp_id << 24; // Destination
+            let reg = IOAPIC_REDIRECTION_TABLE + 2 * (route.gsi - io_apic.gsi_base);
+            write_io_apic(io_apic.addr, reg, low);
+            write_io_apic(io_apic.addr, reg + 1, high);
+        }
+    }
+}
+
+fn read_lapic(addr: u64, reg: u64) -> u32 {
+    unsafe { core::ptr::read_volatile((addr + reg) as *const u32) }
+}
+
+fn write_lapic(addr: u64, reg: u64, value: u32) {
+    unsafe { core::ptr::write_volatile((addr + reg) as *mut u32, value) }
+}
+
+fn read_io_apic(addr: u64, reg: u32) -> u32 {
+    unsafe {
+        core::ptr::write_volatile((addr + IOAPIC_REGSEL) as *mut u32, reg);
+        core::ptr::read_volatile((addr + IOAPIC_WINDOW) as *const u32)
+    }
+}
+
+fn write_io_apic(addr: u64, reg: u32, value: u32) {
+    unsafe {
+        core::ptr::write_volatile((addr + IOAPIC_REGSEL) as *mut u32, reg);
+        core::ptr::write_volatile((addr + IOAPIC_WINDOW) as *mut u32, value);
+    }
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::SeqCst)
+}
+
+pub fn end_of_interrupt() {
+    if let Some(apic) = APIC.get() {
+        write_lapic(apic.lapic_addr, LAPIC_EOI, 0);
+    }
+}
+
+// LAPIC ID of the current CPU
+pub fn id() -> u32 {
+    APIC.get().map_or(0, |apic| read_lapic(apic.lapic_addr, LAPIC_ID) >> 24)
+}
+
+pub fn application_processors() -> &'static [u32] {
+    APIC.get().map_or(&[], |apic| &apic.ap_ids)
+}
+
+// Send an interprocessor interrupt to the CPU with the given LAPIC ID
+fn send_ipi(apic_id: u32, command: u32) {
+    if let Some(apic) = APIC.get() {
+        write_lapic(apic.lapic_addr, LAPIC_ICR_HIGH, apic_id << 24);
+        write_lapic(apic.lapic_addr, LAPIC_ICR_LOW, command);
+        while read_lapic(apic.lapic_addr, LAPIC_ICR_LOW) & LAPIC_ICR_PENDING != 0 {
+            core::hint::spin_loop();
+        }
+    }
+}
+
+// Reset a CPU, that will wait for a startup IPI
+pub fn send_init_ipi(apic_id: u32) {
+    send_ipi(apic_id, LAPIC_ICR_INIT | LAPIC_ICR_ASSERT);
+}
+
+// Start a CPU in real mode at the beginning of the given page
+pub fn send_startup_ipi(apic_id: u32, page: u8) {
+    send_ipi(apic_id, LAPIC_ICR_STARTUP | LAPIC_ICR_ASSERT | page as u32);
+}
+
+pub fn set_irq_mask(irq: u8) {
+    if let Some(apic) = APIC.get() {
+        apic.set_route(irq, true);
+    }
+}
+
+pub fn clear_irq_mask(irq: u8) {
+    if let Some(apic) = APIC.get() {
+        apic.set_route(irq, false);
+    }
+}
+
+// Enable the LAPIC of the current CPU with a periodic timer
+pub fn init_lapic() {
+    if let Some(apic) = APIC.get() {
+        let addr = apic.lapic_addr;
+        write_lapic(addr, LAPIC_TPR, 0); // Accept all interrupts
+        write_lapic(addr, LAPIC_SVR, LAPIC_SVR_ENABLE | SPURIOUS_VECTOR as u32);
+        write_lapic(addr, LAPIC_TIMER_DIVIDE, LAPIC_TIMER_DIVIDE_BY_16);
+        write_lapic(addr, LAPIC_LVT_TIMER, LAPIC_LVT_PERIODIC | TIMER_VECTOR as u32);
+        write_lapic(addr, LAPIC_TIMER_INITIAL_COUNT, apic.timer_count);
+    }
+}
+
+// Count the ticks of the LAPIC timer during a few ticks of the PIT
+fn calibrate_timer(addr: u64) -> u32 {
+    write_lapic(addr, LAPIC_TIMER_DIVIDE, LAPIC_TIMER_DIVIDE_BY_16);
+    write_lapic(addr, LAPIC_LVT_TIMER, LAPIC_LVT_MASKED | TIMER_VECTOR as u32);
+    let start = sys::time::ticks() + 1;
+    while sys::time::ticks() < start {
+        sys::time::halt();
+    }
+    write_lapic(addr, LAPIC_TIMER_INITIAL_COUNT, u32::MAX);
+    while sys::time::ticks() < start + CALIBRATION_TICKS {
+        sys::time::halt();
+    }
+    let count = u32::MAX - read_lapic(addr, LAPIC_TIMER_CURRENT_COUNT);
+    write_lapic(addr, LAPIC_TIMER_INITIAL_COUNT, 0);
+    count / CALIBRATION_TICKS as u32
+}
+
+pub fn init() {
+    let has_apic = CpuId::new().get_feature_info().map_or(false, |f| f.has_apic());
+    if !has_apic {
+        return;
+    }
+    let tables = match unsafe { AcpiTables::search_for_rsdp_bios(MorosAcpiHandler) } {
+        Ok(tables) => tables,
+        Err(_) => return,
+    };
+    let platform_info = match tables.platform_info() {
+        Ok(platform_info) => platform_info,
+        Err(_) => return,
+    };
+    let madt = match platform_info.interrupt_model {
+        InterruptModel::Apic(madt) => madt,
+        _ => return,
+    };
+    if madt.io_apics.is_empty() {
+        return;
+    }
+
+    log!("APIC LAPIC at {:#X}\n", madt.local_apic_address);
+    let lapic_addr = sys::mem::map_registers(madt.local_apic_address);
+    let io_apics: Vec<IoApic> = madt.io_apics.iter().map(|io_apic| {
+        let addr = sys::mem::map_registers(io_apic.address as u64);
+        let gsi_base = io_apic.global_system_interrupt_base;
+        let gsi_count = ((read_io_apic(addr, IOAPIC_VERSION) >> 16) & 0xFF) + 1;
+        log!("APIC IOAPIC {} at {:#X} (GSI {}-{})\n", io_apic.id, io_apic.address, gsi_base, gsi_base + gsi_count - 1);
+        IoApic { addr, gsi_base, gsi_count }
+    }).collect();
+
+    // The ISA IRQs are identity mapped to the GSI unless overridden
+    let mut routes = [Route { gsi: 0, active_low: false, level_triggered: false }; 16];
+    for (irq, route) in routes.iter_mut().enumerate() {
+        route.gsi = irq as u32;
+    }
+    for iso in madt.interrupt_source_overrides.iter() {
+        if let Some(route) = routes.get_mut(iso.isa_source as usize) {
+            route.gsi = iso.global_system_interrupt;
+            route.active_low = matches!(iso.polarity, Polarity::ActiveLow);
+            route.level_triggered = matches!(iso.trigger_mode, TriggerMode::Level);
+        }
+    }
+
+    let bsp_id = read_lapic(lapic_addr, LAPIC_ID) >> 24;
+    let ap_ids = platform_info.processor_info.map_or(Vec::new(), |info| {
+        info.application_processors.iter().filter(|processor| {
+            !matches!(processor.state, ProcessorState::Disabled)
+        }).map(|processor| processor.local_apic_id as u32).collect()
+    });
+
+    let timer_count = calibrate_timer(lapic_addr);
+    let apic = APIC.call_once(|| Apic { lapic_addr, io_apics, routes, timer_count, bsp_id, ap_ids });
+
+    // Switch from the PIC to the IOAPIC with the IRQs enabled so far
+    interrupts::without_interrupts(|| {
+        for io_apic in apic.io_apics.iter() {
+            for i in 0..io_apic.gsi_count {
+                write_io_apic(io_apic.addr, IOAPIC_REDIRECTION_TABLE + 2 * i, IOAPIC_MASKED);
+            }
+        }
+        for irq in 0..16 {
+            apic.set_route(irq, !sys::idt::is_irq_enabled(irq));
+        }
+        sys::pic::disable();
+        init_lapic();
+        ENABLED.store(true, Ordering::SeqCst);
+    });
+}
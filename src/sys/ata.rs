@@ -117,6 +117,7 @@ impl Bus {
         let start = sys::clock::uptime();
         while self.is_busy() {
             if sys::clock::uptime() - start > 1.0 { // Hanged
+                warning!("ATA {} timeout, resetting bus\n", self.id);
                 return self.reset();
             }
 
@@ -213,6 +214,9 @@ impl Bus {
         self.setup(drive, block);
         self.write_command(Command::Read);
         self.busy_loop();
+        if self.is_error() {
+            warning!("ATA {}:{} could not read block {}\n", self.id, drive, block);
+        }
         for i in 0..256 {
             let data = self.read_data();
             buf[i * 2] = data.get_bits(0..8) as u8;
@@ -232,6 +236,9 @@ impl Bus {
             self.write_data(data);
         }
         self.busy_loop();
+        if self.is_error() {
+            warning!("ATA {}:{} could not write block {}\n", self.id, drive, block);
+        }
     }
 }
 
@@ -239,6 +246,10 @@ lazy_static! {
     pub static ref BUSES: Mutex<Vec<Bus>> = Mutex::new(Vec::new());
 }
 
+// The driver is polling the status register, which also clears the interrupt
+// of the drive, so there is nothing else to do than acknowledging it.
+fn interrupt_handler() {}
+
 fn disk_size(sectors: u32) -> (u32, String) {
     let bytes = sectors * 512;
     if bytes >> 20 < 1000 {
@@ -253,6 +264,9 @@ pub fn init() {
         let mut buses = BUSES.lock();
         buses.push(Bus::new(0, 0x1F0, 0x3F6, 14));
         buses.push(Bus::new(1, 0x170, 0x376, 15));
+        for bus in buses.iter() {
+            sys::idt::set_irq_handler(bus.irq, interrupt_handler);
+        }
     }
 
     for (bus, drive, model, serial, size, unit) in list() {
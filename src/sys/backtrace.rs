@@ -0,0 +1,247 @@
+use crate::sys;
+use bootloader::bootinfo::MemoryRegionType;
+use core::fmt;
+use core::ops::Range;
+use core::panic::PanicInfo;
+use object::{Object, ObjectSymbol, SymbolKind};
+use x86_64::{PhysAddr, VirtAddr};
+
+// NOTE: The kernel is compiled with frame pointers (see `.cargo/config.toml`)
+// so each frame starts with the address of the previous frame followed by
+// the return address of the function.
+
+const MAX_FRAMES: usize = 64;
+
+// Print the panic message with the return addresses found on the stack, on
+// the console and on the serial port.
+pub fn report(info: &PanicInfo) {
+    print(format_args!("{}\n", info));
+    print_backtrace();
+}
+
+pub fn print_backtrace() {
+    let mut rbp: u64;
+    let rsp: u64;
+    unsafe { asm!("mov {}, rbp", "mov {}, rsp", out(reg) rbp, out(reg) rsp) };
+    let stack = stack_range(rsp);
+    let elf = kernel_elf();
+    let obj = elf.and_then(|elf| object::File::parse(elf).ok());
+
+    print(format_args!("Backtrace:\n"));
+    for i in 0..MAX_FRAMES {
+        if !is_valid_frame(rbp, rsp, &stack) {
+            break;
+        }
+        let ret = unsafe { *((rbp + 8) as *const u64) };
+        if ret == 0 {
+            break;
+        }
+        // The return address is the instruction following the call
+        match obj.as_ref().and_then(|obj| symbol(obj, ret - 1)) {
+            Some((name, offset)) => {
+                print(format_args!("{:4}: {:#018x} - {}+{:#x}\n", i, ret, Demangle(name), offset + 1));
+            }
+            None => {
+                print(format_args!("{:4}: {:#018x}\n", i, ret));
+            }
+        }
+        let next = unsafe { *(rbp as *const u64) };
+        if next <= rbp { // The stack grows down
+            break;
+        }
+        rbp = next;
+    }
+}
+
+// Range of the stack of the TSS containing the given stack pointer
+fn stack_range(rsp: u64) -> Option<Range<u64>> {
+    let size = sys::process::KERNEL_STACK_SIZE as u64;
+    sys::gdt::stack_tops().iter().copied().filter(|&top| {
+        rsp < top && top - rsp <= size
+    }).min().map(|top| rsp..top)
+}
+
+// A frame must be in the current stack, above the stack pointer. The bounds
+// of the boot stack and of the idle stacks of the APs are not known so their
+// frames must at least be mapped.
+fn is_valid_frame(rbp: u64, rsp: u64, stack: &Option<Range<u64>>) -> bool {
+    if rbp == 0 || rbp % 8 != 0 || rbp < rsp {
+        return false;
+    }
+    match stack {
+        Some(stack) => rbp.checked_add(16).map_or(false, |end| end <= stack.end),
+        None => is_mapped(rbp) && is_mapped(rbp.saturating_add(15)),
+    }
+}
+
+fn is_mapped(addr: u64) -> bool {
+    if unsafe { sys::mem::PHYS_MEM_OFFSET } == 0 {
+        return false; // The memory is not initialized
+    }
+    match VirtAddr::try_new(addr) {
+        Ok(addr) => sys::mem::virt_to_phys(addr).is_some(),
+        Err(_) => false,
+    }
+}
+
+fn print(args: fmt::Arguments) {
+    sys::console::print_fmt(args);
+    if cfg!(feature = "video") {
+        sys::serial::print_fmt(args);
+    }
+}
+
+// The bootloader leaves the ELF file of the kernel in memory, with its symbol
+// table, and marks its frames like those of the loaded kernel.
+fn kernel_elf() -> Option<&'static [u8]> {
+    let memory_map = unsafe { sys::mem::MEMORY_MAP }?;
+    memory_map.iter().filter(|region| region.region_type == MemoryRegionType::Kernel).find_map(|region| {
+        let addr = sys::mem::phys_to_virt(PhysAddr::new(region.range.start_addr()));
+        let size = region.range.end_addr() - region.range.start_addr();
+        let data = unsafe { core::slice::from_raw_parts(addr.as_ptr::<u8>(), size as usize) };
+        if data.starts_with(b"\x7FELF") {
+            Some(data)
+        } else {
+            None
+        }
+    })
+}
+
+// Find the function containing the given address and return its name with
+// the offset of the address in it.
+fn symbol<'a>(obj: &object::File<'a>, addr: u64) -> Option<(&'a str, u64)> {
+    obj.symbols().filter(|sym| sym.kind() == SymbolKind::Text).find_map(|sym| {
+        let start = sym.address();
+        if start <= addr && addr < start + sym.size().max(1) {
+            sym.name().ok().map(|name| (name, addr - start))
+        } else {
+            None
+        }
+    })
+}
+
+// Display a symbol mangled with the legacy scheme of Rust like
+// "_ZN5moros3sys2fs4File4open17h0123456789abcdefE" as "moros::sys::fs::File::open"
+struct Demangle<'a>(&'a str);
+
+impl<'a> Demangle<'a> {
+    fn components(&self) -> Option<Components<'a>> {
+        let inner = self.0.strip_prefix("_ZN")?.strip_suffix('E')?;
+        if Components(inner).all(|c| c.is_some()) {
+            Some(Components(inner))
+        } else {
+            None
+        }
+    }
+}
+
+impl<'a> fmt::Display for Demangle<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let components = match self.components() {
+            Some(components) => components,
+            None => return write!(f, "{}", self.0),
+        };
+        for (i, c) in components.flatten().enumerate() {
+            if is_hash(c) {
+                continue;
+            }
+            if i > 0 {
+                write!(f, "::")?;
+            }
+            write_unescaped(f, c)?;
+        }
+        Ok(())
+    }
+}
+
+// Iterator over the length prefixed components of a mangled symbol
+struct Components<'a>(&'a str);
+
+impl<'a> Iterator for Components<'a> {
+    type Item = Option<&'a str>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.0.is_empty() {
+            return None;
+        }
+        let n = self.0.bytes().take_while(|b| b.is_ascii_digit()).count();
+        let len: usize = match self.0[0..n].parse() {
+            Ok(len) if n + len <= self.0.len() && self.0.is_char_boundary(n + len) => len,
+            _ => {
+                self.0 = "";
+                return Some(None);
+            }
+        };
+        let component = &self.0[n..(n + len)];
+        self.0 = &self.0[(n + len)..];
+        Some(Some(component))
+    }
+}
+
+fn is_hash(component: &str) -> bool {
+    component.len() == 17 && component.starts_with('h') && component[1..].bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+fn write_unescaped(f: &mut fmt::Formatter, component: &str) -> fmt::Result {
+    // A leading underscore is added to components starting with an escape
+    let mut s = if component.starts_with("_$") { &component[1..] } else { component };
+    while !s.is_empty() {
+        if let Some(rest) = s.strip_prefix("..") {
+            write!(f, "::")?;
+            s = rest;
+        } else if let Some(rest) = s.strip_prefix('$') {
+            let end = match rest.find('$') {
+                Some(end) => end,
+                None => return write!(f, "{}", s),
+            };
+            let c = match &rest[0..end] {
+                "SP" => "@",
+                "BP" => "*",
+                "RF" => "&",
+                "LT" => "<",
+                "GT" => ">",
+                "LP" => "(",
+                "RP" => ")",
+                "C" => ",",
+                "u20" => " ",
+                "u27" => "'",
+                "u5b" => "[",
+                "u5d" => "]",
+                "u7b" => "{",
+                "u7d" => "}",
+                "u7e" => "~",
+                other => other,
+            };
+            write!(f, "{}", c)?;
+            s = &rest[(end + 1)..];
+        } else {
+            let end = s.find(|c| c == '$' || c == '.').unwrap_or(s.len()).max(1);
+            write!(f, "{}", &s[0..end])?;
+            s = &s[end..];
+        }
+    }
+    Ok(())
+}
+
+#[test_case]
+fn test_demangle() {
+    use alloc::format;
+
+    let name = "_ZN5moros3sys2fs4File4open17h0123456789abcdefE";
+    assert_eq!(format!("{}", Demangle(name)), "moros::sys::fs::File::open");
+    let name = "_ZN4core3ptr40drop_in_place$LT$moros..sys..fs..Dir$GT$17h0123456789abcdefE";
+    assert_eq!(format!("{}", Demangle(name)), "core::ptr::drop_in_place<moros::sys::fs::Dir>");
+    assert_eq!(format!("{}", Demangle("memcpy")), "memcpy");
+    assert_eq!(format!("{}", Demangle("_ZN99fooE")), "_ZN99fooE");
+}
+
+#[test_case]
+fn test_is_valid_frame() {
+    let stack = Some(0x1000..0x2000);
+    assert!(is_valid_frame(0x1800, 0x1000, &stack));
+    assert!(!is_valid_frame(0, 0x1000, &stack));
+    assert!(!is_valid_frame(0x1804, 0x1000, &stack)); // Misaligned
+    assert!(!is_valid_frame(0x0800, 0x1000, &stack)); // Below the stack pointer
+    assert!(!is_valid_frame(0x1FF8, 0x1000, &stack)); // Return address outside
+    assert!(!is_valid_frame(u64::MAX - 7, 0x1000, &stack));
+}
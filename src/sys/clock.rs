@@ -1,11 +1,21 @@
 use crate::sys;
 use crate::sys::cmos::CMOS;
+use core::sync::atomic::{AtomicI64, Ordering};
 
 const DAYS_BEFORE_MONTH: [u64; 13] = [0, 31, 59, 90, 120, 151, 181, 212, 243, 273, 304, 334, 365];
 
+// Nanoseconds added to the time of the RTC by the realtime clock
+static REALTIME_OFFSET: AtomicI64 = AtomicI64::new(0);
+
+// Nanoseconds since boot
+// NOTE: This clock is monotonic
+pub fn monotonic() -> u64 {
+    sys::time::nanoseconds()
+}
+
 // NOTE: This clock is monotonic
 pub fn uptime() -> f64 {
-    sys::time::time_between_ticks() * sys::time::ticks() as f64
+    monotonic() as f64 / 1e9
 }
 
 // NOTE: This clock is not monotonic
@@ -19,10 +29,21 @@ pub fn realtime() -> f64 {
                   +    60 * rtc.minute as u64
                   +         rtc.second as u64;
 
-    let fract = sys::time::time_between_ticks()
-              * (sys::time::ticks() - sys::time::last_rtc_update()) as f64;
+    let fract = monotonic().saturating_sub(sys::time::last_rtc_update()) as f64 / 1e9;
+    let offset = REALTIME_OFFSET.load(Ordering::Relaxed) as f64 / 1e9;
+
+    (timestamp as f64) + fract + offset
+}
+
+// Shift the realtime clock by the given number of seconds without changing
+// the RTC.
+pub fn adjust_realtime(seconds: f64) {
+    REALTIME_OFFSET.fetch_add((seconds * 1e9) as i64, Ordering::Relaxed);
+}
 
-    (timestamp as f64) + fract
+// The realtime clock follows the RTC again after it has been set
+pub fn reset_realtime_offset() {
+    REALTIME_OFFSET.store(0, Ordering::Relaxed);
 }
 
 fn days_before_year(year: u64) -> u64 {
@@ -53,7 +74,26 @@ fn test_uptime() {
     assert!(uptime() > 0.0);
 }
 
+#[test_case]
+fn test_monotonic() {
+    let a = monotonic();
+    sys::time::nanowait(1000);
+    let b = monotonic();
+    assert!(b > a);
+}
+
 #[test_case]
 fn test_realtime() {
     assert!(realtime() > 1234567890.0);
 }
+
+#[test_case]
+fn test_adjust_realtime() {
+    let a = realtime();
+    adjust_realtime(3600.0);
+    let b = realtime();
+    adjust_realtime(-3600.0);
+    let c = realtime();
+    assert!(b - a > 3599.0 && b - a < 3601.0);
+    assert!(c - a > -1.0 && c - a < 1.0);
+}
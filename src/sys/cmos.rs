@@ -1,4 +1,10 @@
+use crate::sys;
+use crate::sys::fs::FileIO;
+use alloc::format;
+use alloc::string::String;
+use core::fmt;
 use core::hint::spin_loop;
+use core::str::FromStr;
 
 use bit_field::BitField;
 use x86_64::instructions::interrupts;
@@ -24,7 +30,7 @@ enum Interrupt {
     Update = 1 << 4,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct RTC {
     pub year: u16,
     pub month: u8,
@@ -34,6 +40,87 @@ pub struct RTC {
     pub second: u8,
 }
 
+impl RTC {
+    pub fn new() -> Self {
+        CMOS::new().rtc()
+    }
+}
+
+impl fmt::Display for RTC {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f, "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+            self.year, self.month, self.day,
+            self.hour, self.minute, self.second
+        )
+    }
+}
+
+// Parse a date like "2001-08-23T14:55:02" within the range of the RTC
+impl FromStr for RTC {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.len() != 19 || !s.is_ascii() {
+            return Err(());
+        }
+        let b = s.as_bytes();
+        if b[4] != b'-' || b[7] != b'-' || !(b[10] == b'T' || b[10] == b' ') || b[13] != b':' || b[16] != b':' {
+            return Err(());
+        }
+        let rtc = RTC {
+            year: s[0..4].parse().map_err(|_| ())?,
+            month: s[5..7].parse().map_err(|_| ())?,
+            day: s[8..10].parse().map_err(|_| ())?,
+            hour: s[11..13].parse().map_err(|_| ())?,
+            minute: s[14..16].parse().map_err(|_| ())?,
+            second: s[17..19].parse().map_err(|_| ())?,
+        };
+        let is_valid = (2000..2100).contains(&rtc.year)
+                    && (1..=12).contains(&rtc.month)
+                    && (1..=days_in_month(rtc.year, rtc.month)).contains(&rtc.day)
+                    && rtc.hour < 24 && rtc.minute < 60 && rtc.second < 60;
+        if is_valid {
+            Ok(rtc)
+        } else {
+            Err(())
+        }
+    }
+}
+
+fn days_in_month(year: u16, month: u8) -> u8 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+// Device reading the date of the RTC, or setting it when a date is written
+impl FileIO for RTC {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, ()> {
+        *self = CMOS::new().rtc();
+        let date = format!("{}\n", self);
+        let n = date.len();
+        if buf.len() < n {
+            return Err(());
+        }
+        buf[0..n].copy_from_slice(date.as_bytes());
+        Ok(n)
+    }
+
+    fn write(&mut self, buf: &[u8]) -> Result<usize, ()> {
+        let date = String::from_utf8_lossy(buf);
+        *self = date.parse()?;
+        CMOS::new().set_rtc(self);
+        sys::time::reset_last_rtc_update();
+        sys::clock::reset_realtime_offset();
+        Ok(buf.len())
+    }
+}
+
 pub struct CMOS {
     addr: Port<u8>,
     data: Port<u8>,
@@ -82,8 +169,9 @@ impl CMOS {
             rtc.year = (rtc.year & 0x0F) + ((rtc.year / 16) * 10);
         }
 
-        if (b & 0x02 == 0) && (rtc.hour & 0x80 == 0) { // 12 hour format
-            rtc.hour = ((rtc.hour & 0x7F) + 12) % 24;
+        if b & 0x02 == 0 { // 12 hour format
+            let is_pm = rtc.hour & 0x80 != 0;
+            rtc.hour = ((rtc.hour & 0x7F) % 12) + if is_pm { 12 } else { 0 };
         }
 
         rtc.year += 2000; // TODO: Don't forget to change this next century
@@ -91,6 +179,52 @@ impl CMOS {
         rtc
     }
 
+    // Write the RTC in the format given by the status register B, with the
+    // updates inhibited while the registers are written.
+    pub fn set_rtc(&mut self, rtc: &RTC) {
+        interrupts::without_interrupts(|| {
+            self.wait_end_of_update();
+            let b = self.read_register(Register::B);
+
+            let mut second = rtc.second;
+            let mut minute = rtc.minute;
+            let mut hour = rtc.hour;
+            let mut day = rtc.day;
+            let mut month = rtc.month;
+            let mut year = rtc.year.saturating_sub(2000) as u8;
+
+            let mut is_pm = false;
+            if b & 0x02 == 0 { // 12 hour format
+                is_pm = hour >= 12;
+                hour = if hour % 12 == 0 { 12 } else { hour % 12 };
+            }
+
+            if b & 0x04 == 0 { // BCD Mode
+                second = (second / 10) * 16 + (second % 10);
+                minute = (minute / 10) * 16 + (minute % 10);
+                hour = (hour / 10) * 16 + (hour % 10);
+                day = (day / 10) * 16 + (day % 10);
+                month = (month / 10) * 16 + (month % 10);
+                year = (year / 10) * 16 + (year % 10);
+            }
+
+            if is_pm {
+                hour |= 0x80;
+            }
+
+            self.disable_nmi();
+            self.write_register(Register::B, b | 0x80); // Inhibit updates
+            self.write_register(Register::Second, second);
+            self.write_register(Register::Minute, minute);
+            self.write_register(Register::Hour, hour);
+            self.write_register(Register::Day, day);
+            self.write_register(Register::Month, month);
+            self.write_register(Register::Year, year);
+            self.write_register(Register::B, b & 0x7F);
+            self.enable_nmi();
+        });
+    }
+
     pub fn enable_periodic_interrupt(&mut self) {
         self.enable_interrupt(Interrupt::Periodic);
     }
@@ -160,6 +294,13 @@ impl CMOS {
         }
     }
 
+    fn write_register(&mut self, reg: Register, value: u8) {
+        unsafe {
+            self.addr.write(reg as u8);
+            self.data.write(value);
+        }
+    }
+
     fn enable_nmi(&mut self) {
         unsafe {
             let prev = self.addr.read();
@@ -174,3 +315,16 @@ impl CMOS {
         }
     }
 }
+
+#[test_case]
+fn test_rtc_from_str() {
+    let rtc = RTC { year: 2001, month: 8, day: 23, hour: 14, minute: 55, second: 2 };
+    assert_eq!("2001-08-23T14:55:02".parse(), Ok(rtc.clone()));
+    assert_eq!("2001-08-23 14:55:02\n".parse(), Ok(rtc.clone()));
+    assert_eq!(format!("{}", rtc), "2001-08-23T14:55:02");
+    assert_eq!("2000-02-29T00:00:00".parse::<RTC>().map(|rtc| rtc.day), Ok(29));
+    assert_eq!("2001-02-29T00:00:00".parse::<RTC>(), Err(()));
+    assert_eq!("1999-12-31T23:59:59".parse::<RTC>(), Err(()));
+    assert_eq!("2001-08-23T24:00:00".parse::<RTC>(), Err(()));
+    assert_eq!("2001-8-23T14:55:02".parse::<RTC>(), Err(()));
+}
@@ -111,7 +111,10 @@ pub fn key_handle(key: char) {
             }
         }
     } else {
-        stdin.push(key);
+        // The key is not kept in the input when it is delivered as a signal
+        if key != ETX_KEY || !sys::process::interrupt_foreground() {
+            stdin.push(key);
+        }
         if is_echo_enabled() {
             match key {
                 ETX_KEY => print_fmt(format_args!("^C")),
@@ -145,7 +148,7 @@ pub fn read_char() -> char {
     sys::console::disable_echo();
     sys::console::enable_raw();
     loop {
-        sys::time::halt();
+        sys::time::halt_preemptible();
         let res = interrupts::without_interrupts(|| {
             let mut stdin = STDIN.lock();
             if !stdin.is_empty() {
@@ -159,12 +162,17 @@ pub fn read_char() -> char {
             sys::console::disable_raw();
             return c;
         }
+        if sys::process::has_pending_signal() {
+            sys::console::enable_echo();
+            sys::console::disable_raw();
+            return '\0';
+        }
     }
 }
 
 pub fn read_line() -> String {
     loop {
-        sys::time::halt();
+        sys::time::halt_preemptible();
         let res = interrupts::without_interrupts(|| {
             let mut stdin = STDIN.lock();
             match stdin.chars().next_back() {
@@ -181,6 +189,10 @@ pub fn read_line() -> String {
         if let Some(line) = res {
             return line;
         }
+        if sys::process::has_pending_signal() {
+            drain();
+            return String::new();
+        }
     }
 }
 
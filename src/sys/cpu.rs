@@ -1,4 +1,11 @@
+use core::sync::atomic::{AtomicUsize, Ordering};
 use raw_cpuid::CpuId;
+use x86_64::instructions::interrupts;
+
+pub const MAX_CPUS: usize = 16;
+
+// Number of CPUs running, with the bootstrap processor as the first one
+static COUNT: AtomicUsize = AtomicUsize::new(1);
 
 pub fn init() {
     let cpuid = CpuId::new();
@@ -16,3 +23,45 @@ pub fn init() {
         log!("CPU {} MHz\n", processor_base_frequency);
     }
 }
+
+pub fn count() -> usize {
+    COUNT.load(Ordering::SeqCst)
+}
+
+// Index of the current CPU, from 0 to `count() - 1`, cached in its local data
+// by `sys::gdt` when the tables are loaded.
+//
+// NOTE: The kernel GS base holding the local data is only swapped in by the
+// SYSCALL entry point with interrupts disabled, so we do the same here to
+// avoid being interrupted by a handler doing it too. It must not be called by
+// an application processor before its tables are loaded.
+pub fn id() -> usize {
+    if count() == 1 {
+        return 0;
+    }
+    interrupts::without_interrupts(|| {
+        let cpu: u64;
+        unsafe {
+            asm!(
+                "swapgs",
+                "mov {}, gs:[0x28]",
+                "swapgs",
+                out(reg) cpu,
+                options(nostack, preserves_flags, readonly)
+            );
+        }
+        cpu as usize
+    })
+}
+
+// Called by each application processor when it is ready, after its tables
+// have been loaded with the next index.
+pub fn add_cpu() {
+    COUNT.fetch_add(1, Ordering::SeqCst);
+}
+
+#[test_case]
+fn test_cpu_id() {
+    assert!(count() > 0);
+    assert!(id() < count());
+}
@@ -0,0 +1,121 @@
+use core::convert::TryInto;
+use core::sync::atomic::{AtomicBool, Ordering};
+use raw_cpuid::CpuId;
+use x86_64::registers::control::{Cr0, Cr0Flags, Cr4, Cr4Flags};
+
+// The kernel is compiled with soft floats so it never touches the x87, MMX,
+// or SSE registers itself. Only user programs can use them, and their state
+// is saved and restored by the scheduler when switching between processes.
+
+// Enough for the x87, SSE and AVX components of the XSAVE area
+const STATE_SIZE: usize = 1024;
+
+const XCR0_X87: u64 = 1 << 0;
+const XCR0_SSE: u64 = 1 << 1;
+const XCR0_AVX: u64 = 1 << 2;
+
+const DEFAULT_FCW: u16 = 0x037F; // All x87 exceptions masked
+const DEFAULT_MXCSR: u32 = 0x1F80; // All SSE exceptions masked
+
+static HAS_XSAVE: AtomicBool = AtomicBool::new(false);
+
+#[repr(C, align(64))]
+#[derive(Clone)]
+pub struct FpuState {
+    data: [u8; STATE_SIZE],
+}
+
+impl FpuState {
+    // Create the state given to a new process, which is the initial state of
+    // the registers after a reset.
+    pub fn new() -> Self {
+        let mut data = [0; STATE_SIZE];
+        data[0..2].copy_from_slice(&DEFAULT_FCW.to_le_bytes());
+        data[24..28].copy_from_slice(&DEFAULT_MXCSR.to_le_bytes());
+        Self { data }
+    }
+
+    pub fn fcw(&self) -> u16 {
+        u16::from_le_bytes(self.data[0..2].try_into().unwrap())
+    }
+
+    pub fn mxcsr(&self) -> u32 {
+        u32::from_le_bytes(self.data[24..28].try_into().unwrap())
+    }
+
+    // Save the registers of the CPU in this state
+    pub fn save(&mut self) {
+        let ptr = self.data.as_mut_ptr();
+        unsafe {
+            if HAS_XSAVE.load(Ordering::Relaxed) {
+                asm!("xsave64 [{}]", in(reg) ptr, in("eax") u32::MAX, in("edx") u32::MAX);
+            } else {
+                asm!("fxsave64 [{}]", in(reg) ptr);
+            }
+        }
+    }
+
+    // Load the registers of the CPU from this state
+    pub fn restore(&self) {
+        let ptr = self.data.as_ptr();
+        unsafe {
+            if HAS_XSAVE.load(Ordering::Relaxed) {
+                asm!("xrstor64 [{}]", in(reg) ptr, in("eax") u32::MAX, in("edx") u32::MAX);
+            } else {
+                asm!("fxrstor64 [{}]", in(reg) ptr);
+            }
+        }
+    }
+}
+
+impl Default for FpuState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub fn init() {
+    let features = CpuId::new().get_feature_info();
+    let has_xsave = features.as_ref().map_or(false, |f| f.has_xsave());
+    let has_avx = features.as_ref().map_or(false, |f| f.has_avx());
+
+    unsafe {
+        // Use the native x87 error reporting without emulation, and let the
+        // WAIT instruction check the TS flag.
+        Cr0::update(|flags| {
+            flags.remove(Cr0Flags::EMULATE_COPROCESSOR | Cr0Flags::TASK_SWITCHED);
+            flags.insert(Cr0Flags::MONITOR_COPROCESSOR | Cr0Flags::NUMERIC_ERROR);
+        });
+
+        // Enable SSE with FXSAVE and FXRSTOR, and the SIMD exceptions
+        Cr4::update(|flags| {
+            flags.insert(Cr4Flags::OSFXSR | Cr4Flags::OSXMMEXCPT_ENABLE);
+            if has_xsave {
+                flags.insert(Cr4Flags::OSXSAVE);
+            }
+        });
+
+        if has_xsave {
+            // Select the components saved by XSAVE in the XCR0 register
+            let xcr0 = XCR0_X87 | XCR0_SSE | if has_avx { XCR0_AVX } else { 0 };
+            asm!("xsetbv", in("ecx") 0, in("eax") xcr0 as u32, in("edx") (xcr0 >> 32) as u32);
+        }
+
+        asm!("fninit");
+        asm!("ldmxcsr [{}]", in(reg) &DEFAULT_MXCSR as *const u32);
+    }
+    HAS_XSAVE.store(has_xsave, Ordering::Relaxed);
+}
+
+#[test_case]
+fn test_fpu_state() {
+    let state = FpuState::new();
+    assert_eq!(state.fcw(), DEFAULT_FCW);
+    assert_eq!(state.mxcsr(), DEFAULT_MXCSR);
+
+    // The kernel doesn't change the state of the registers
+    let mut state = FpuState { data: [0; STATE_SIZE] };
+    state.save();
+    assert_eq!(state.fcw(), DEFAULT_FCW);
+    assert_eq!(state.mxcsr(), DEFAULT_MXCSR);
+}
@@ -3,7 +3,9 @@ use super::dir::Dir;
 use super::file::File;
 use super::block::Block;
 
+use crate::sys::cmos::RTC;
 use crate::sys::console::Console;
+use crate::sys::log::Log;
 use crate::sys::random::Random;
 
 #[repr(u8)]
@@ -11,6 +13,8 @@ pub enum DeviceType {
     File = 0,
     Console = 1,
     Random = 2,
+    Log = 3,
+    RTC = 4,
 }
 
 #[derive(Debug, Clone)]
@@ -18,14 +22,19 @@ pub enum Device {
     File(File),
     Console(Console),
     Random(Random),
+    Log(Log),
+    RTC(RTC),
 }
 
 impl Device {
-    fn new(i: u8) -> Self {
+    // The type is read from the disk so it cannot be trusted
+    fn new(i: u8) -> Option<Self> {
         match i {
-            i if i == DeviceType::Console as u8 => Device::Console(Console::new()),
-            i if i == DeviceType::Random as u8 => Device::Random(Random::new()),
-            _ => unimplemented!(),
+            i if i == DeviceType::Console as u8 => Some(Device::Console(Console::new())),
+            i if i == DeviceType::Random as u8 => Some(Device::Random(Random::new())),
+            i if i == DeviceType::Log as u8 => Some(Device::Log(Log::new())),
+            i if i == DeviceType::RTC as u8 => Some(Device::RTC(RTC::new())),
+            _ => None,
         }
     }
 
@@ -50,7 +59,7 @@ impl Device {
                 if dir_entry.is_device() {
                     let block = Block::read(dir_entry.addr());
                     let data = block.data();
-                    return Some(Self::new(data[0]));
+                    return Self::new(data[0]);
                 }
             }
         }
@@ -64,6 +73,8 @@ impl FileIO for Device {
             Device::File(io) => io.read(buf),
             Device::Console(io) => io.read(buf),
             Device::Random(io) => io.read(buf),
+            Device::Log(io) => io.read(buf),
+            Device::RTC(io) => io.read(buf),
         }
     }
     fn write(&mut self, buf: &[u8]) -> Result<usize, ()> {
@@ -71,6 +82,21 @@ impl FileIO for Device {
             Device::File(io) => io.write(buf),
             Device::Console(io) => io.write(buf),
             Device::Random(io) => io.write(buf),
+            Device::Log(io) => io.write(buf),
+            Device::RTC(io) => io.write(buf),
         }
     }
 }
+
+#[test_case]
+fn test_device_open() {
+    super::mount_mem();
+    super::format_mem();
+    let mut dev = Device::create("/random").unwrap();
+    assert_eq!(dev.write(&[DeviceType::Random as u8]), Ok(1));
+    assert!(Device::open("/random").is_some());
+    let mut dev = Device::create("/unknown").unwrap();
+    assert_eq!(dev.write(&[0xFF]), Ok(1));
+    assert!(Device::open("/unknown").is_none());
+    super::dismount();
+}
@@ -93,6 +93,19 @@ impl Dir {
     }
 
     fn create_entry(&self, kind: FileType, name: &str) -> Option<DirEntry> {
+        let entry_block = Block::alloc()?;
+        let entry_addr = entry_block.addr();
+        let entry_size = 0u32;
+        let entry_time = sys::clock::realtime() as u64;
+        let res = self.add_entry(kind, entry_addr, entry_size, entry_time, name);
+        if res.is_none() {
+            BlockBitmap::free(entry_addr);
+        }
+        res
+    }
+
+    // Add an entry pointing to existing data
+    pub fn add_entry(&self, kind: FileType, entry_addr: u32, entry_size: u32, entry_time: u64, name: &str) -> Option<DirEntry> {
         if self.find(name).is_some() {
             return None;
         }
@@ -115,11 +128,7 @@ impl Dir {
         }
 
         // Create a new entry
-        let entry_block = Block::alloc().unwrap();
         let entry_kind = kind as u8;
-        let entry_addr = entry_block.addr();
-        let entry_size = 0u32;
-        let entry_time = sys::clock::realtime() as u64;
         let entry_name = truncate(name, u8::MAX as usize);
         let n = entry_name.len();
         let i = entries.block_data_offset();
@@ -140,6 +149,22 @@ impl Dir {
     // Deleting an entry is done by setting the entry address to 0
     // TODO: If the entry is a directory, remove its entries recursively
     pub fn delete_entry(&mut self, name: &str) -> Result<(), ()> {
+        let entry = self.remove_entry(name)?;
+
+        // Freeing entry blocks
+        let mut entry_block = Block::read(entry.addr());
+        loop {
+            BlockBitmap::free(entry_block.addr());
+            match entry_block.next() {
+                Some(next_block) => entry_block = next_block,
+                None => break,
+            }
+        }
+        Ok(())
+    }
+
+    // Remove an entry without freeing its data
+    pub fn remove_entry(&mut self, name: &str) -> Result<DirEntry, ()> {
         let mut entries = self.entries();
         for entry in &mut entries {
             if entry.name() == name {
@@ -151,18 +176,7 @@ impl Dir {
                 data[i + 3] = 0;
                 data[i + 4] = 0;
                 entries.block.write();
-
-                // Freeing entry blocks
-                let mut entry_block = Block::read(entry.addr());
-                loop {
-                    BlockBitmap::free(entry_block.addr());
-                    match entry_block.next() {
-                        Some(next_block) => entry_block = next_block,
-                        None => break,
-                    }
-                }
-
-                return Ok(());
+                return Ok(entry);
             }
         }
         Err(())
@@ -1,6 +1,8 @@
 use super::{dirname, filename, realpath, FileType};
 use super::dir::Dir;
 use alloc::string::String;
+use alloc::vec::Vec;
+use core::convert::TryInto;
 
 #[derive(Clone)]
 pub struct DirEntry {
@@ -79,6 +81,91 @@ impl DirEntry {
     pub fn stat(&self) -> FileStat {
         FileStat { kind: self.kind, size: self.size, time: self.time }
     }
+
+    pub fn info(&self) -> FileInfo {
+        FileInfo { kind: self.kind, size: self.size, time: self.time, name: self.name.clone() }
+    }
+}
+
+// Directory entry given to user programs by the READDIR syscall, serialized
+// like on the disk but without the address of the entry.
+#[derive(Debug, Clone)]
+pub struct FileInfo {
+    kind: FileType,
+    size: u32,
+    time: u64,
+    name: String,
+}
+
+impl FileInfo {
+    pub fn empty_len() -> usize {
+        1 + 4 + 8 + 1
+    }
+
+    pub fn len(&self) -> usize {
+        Self::empty_len() + self.name.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        Self::empty_len() == self.len()
+    }
+
+    pub fn kind(&self) -> FileType {
+        self.kind
+    }
+
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    pub fn time(&self) -> u64 {
+        self.time
+    }
+
+    pub fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.kind == FileType::Dir
+    }
+
+    pub fn is_file(&self) -> bool {
+        self.kind == FileType::File
+    }
+
+    pub fn is_device(&self) -> bool {
+        self.kind == FileType::Device
+    }
+
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let n = self.name.len();
+        let mut buf = Vec::with_capacity(self.len());
+        buf.push(self.kind as u8);
+        buf.extend_from_slice(&self.size.to_be_bytes());
+        buf.extend_from_slice(&self.time.to_be_bytes());
+        buf.push(n as u8);
+        buf.extend_from_slice(self.name.as_bytes());
+        buf
+    }
+
+    pub fn from_bytes(buf: &[u8]) -> Option<Self> {
+        if buf.len() < Self::empty_len() {
+            return None;
+        }
+        let kind = match buf[0] {
+            0 => FileType::Dir,
+            1 => FileType::File,
+            2 => FileType::Device,
+            _ => return None,
+        };
+        let size = u32::from_be_bytes(buf[1..5].try_into().ok()?);
+        let time = u64::from_be_bytes(buf[5..13].try_into().ok()?);
+        let n = buf[13] as usize;
+        let name = buf.get(14..(14 + n))?;
+        let name = String::from_utf8_lossy(name).into();
+        Some(Self { kind, size, time, name })
+    }
 }
 
 #[derive(Debug)]
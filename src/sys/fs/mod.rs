@@ -9,13 +9,18 @@ mod read_dir;
 
 pub use device::{Device, DeviceType};
 pub use dir::Dir;
-pub use dir_entry::FileStat;
+pub use dir_entry::{FileInfo, FileStat};
 pub use file::{File, SeekFrom};
 pub use block_device::{format_ata, format_mem, is_mounted, mount_ata, mount_mem, dismount};
-pub use crate::api::fs::{dirname, filename, realpath, FileIO};
+pub use crate::api::fs::{dirname, filename, FileIO};
 
+use crate::sys;
+use crate::sys::pipe::Pipe;
 use block_bitmap::BlockBitmap;
 use dir_entry::DirEntry;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
 
 #[repr(u8)]
 pub enum OpenFlag {
@@ -61,6 +66,50 @@ pub fn stat(pathname: &str) -> Option<FileStat> {
     DirEntry::open(pathname).map(|e| e.stat())
 }
 
+// Transform "foo.txt" into "/path/to/foo.txt" with the directory of the
+// current process. Unlike `api::fs::realpath` it can be used while handling
+// a syscall.
+pub fn realpath(pathname: &str) -> String {
+    if pathname.starts_with('/') {
+        pathname.into()
+    } else {
+        let dirname = sys::process::dir();
+        let sep = if dirname.ends_with('/') { "" } else { "/" };
+        format!("{}{}{}", dirname, sep, pathname)
+    }
+}
+
+pub fn read_dir(pathname: &str) -> Option<Vec<FileInfo>> {
+    Dir::open(pathname).map(|dir| dir.entries().map(|e| e.info()).collect())
+}
+
+// Delete a file, a device, or an empty directory
+pub fn delete(pathname: &str) -> Result<(), ()> {
+    if let Some(dir) = Dir::open(pathname) {
+        if dir.entries().next().is_some() {
+            return Err(());
+        }
+    }
+    let pathname = realpath(pathname);
+    let mut dir = Dir::open(dirname(&pathname)).ok_or(())?;
+    dir.delete_entry(filename(&pathname))
+}
+
+// Move an entry to another path, possibly in another directory, without
+// copying its data.
+pub fn rename(from: &str, to: &str) -> Result<(), ()> {
+    let from = realpath(from);
+    let to = realpath(to);
+    if to.starts_with(&format!("{}/", from)) {
+        return Err(()); // A directory cannot be moved inside itself
+    }
+    let entry = DirEntry::open(&from).ok_or(())?;
+    let mut src = Dir::open(dirname(&from)).ok_or(())?;
+    let dst = Dir::open(dirname(&to)).ok_or(())?;
+    dst.add_entry(entry.kind(), entry.addr(), entry.size(), entry.time(), filename(&to)).ok_or(())?;
+    src.remove_entry(filename(&from)).map(|_| ())
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum FileType {
     Dir = 0,
@@ -73,6 +122,7 @@ pub enum Resource {
     Dir(Dir),
     File(File),
     Device(Device),
+    Pipe(Pipe),
 }
 
 impl FileIO for Resource {
@@ -81,6 +131,7 @@ impl FileIO for Resource {
             Resource::Dir(io) => io.read(buf),
             Resource::File(io) => io.read(buf),
             Resource::Device(io) => io.read(buf),
+            Resource::Pipe(io) => io.read(buf),
         }
     }
 
@@ -89,6 +140,7 @@ impl FileIO for Resource {
             Resource::Dir(io) => io.write(buf),
             Resource::File(io) => io.write(buf),
             Resource::Device(io) => io.write(buf),
+            Resource::Pipe(io) => io.write(buf),
         }
     }
 }
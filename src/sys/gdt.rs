@@ -1,7 +1,12 @@
+use crate::sys;
+use crate::sys::cpu::MAX_CPUS;
+use alloc::boxed::Box;
+use alloc::vec;
 use lazy_static::lazy_static;
 use x86_64::VirtAddr;
 use x86_64::instructions::segmentation::{CS, DS, Segment};
 use x86_64::instructions::tables::load_tss;
+use x86_64::registers::model_specific::KernelGsBase;
 use x86_64::structures::gdt::{Descriptor, GlobalDescriptorTable, SegmentSelector};
 use x86_64::structures::tss::TaskStateSegment;
 
@@ -10,6 +15,9 @@ pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
 pub const PAGE_FAULT_IST_INDEX: u16 = 1;
 pub const GENERAL_PROTECTION_FAULT_IST_INDEX: u16 = 2;
 
+// The tables of the bootstrap processor are loaded before the heap is ready
+// so their stacks are static, while each application processor gets its own
+// tables with stacks allocated on the heap.
 lazy_static! {
     static ref TSS: TaskStateSegment = {
         let mut tss = TaskStateSegment::new();
@@ -34,32 +42,118 @@ lazy_static! {
 }
 
 lazy_static! {
-    pub static ref GDT: (GlobalDescriptorTable, Selectors) = {
-        let mut gdt = GlobalDescriptorTable::new();
+    pub static ref GDT: (GlobalDescriptorTable, Selectors) = create_gdt(&TSS);
+}
 
-        let tss = gdt.add_entry(Descriptor::tss_segment(&TSS));
-        let code = gdt.add_entry(Descriptor::kernel_code_segment());
-        let data = gdt.add_entry(Descriptor::kernel_data_segment());
-        let user_code = gdt.add_entry(Descriptor::user_code_segment());
-        let user_data = gdt.add_entry(Descriptor::user_data_segment());
+// NOTE: The segments are added in the same order in every GDT so the
+// selectors given by `GDT` are valid on all the CPUs.
+fn create_gdt(tss: &'static TaskStateSegment) -> (GlobalDescriptorTable, Selectors) {
+    let mut gdt = GlobalDescriptorTable::new();
 
-        (gdt, Selectors { tss, code, data, user_code, user_data })
-    };
+    let tss = gdt.add_entry(Descriptor::tss_segment(tss));
+    let code = gdt.add_entry(Descriptor::kernel_code_segment());
+    let data = gdt.add_entry(Descriptor::kernel_data_segment());
+    // NOTE: The user data segment must be right before the user code
+    // segment for SYSRET.
+    let user_data = gdt.add_entry(Descriptor::user_data_segment());
+    let user_code = gdt.add_entry(Descriptor::user_code_segment());
+
+    (gdt, Selectors { tss, code, data, user_code, user_data })
 }
 
 pub struct Selectors {
     tss: SegmentSelector,
-    code: SegmentSelector,
-    data: SegmentSelector,
+    pub code: SegmentSelector,
+    pub data: SegmentSelector,
     pub user_code: SegmentSelector,
     pub user_data: SegmentSelector,
 }
 
-pub fn init() {
-    GDT.0.load();
+// Data of the CPU accessed with the GS segment by the SYSCALL entry point
+// and by `sys::cpu::id`, the offsets of the fields are used in their assembly
+// code.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct CpuLocal {
+    kernel_stack: u64, // 0x00
+    user_stack: u64,   // 0x08
+    user_data: u64,    // 0x10
+    user_code: u64,    // 0x18
+    tss: *mut TaskStateSegment, // 0x20
+    cpu: u64,          // 0x28
+}
+
+impl CpuLocal {
+    const fn new() -> Self {
+        Self { kernel_stack: 0, user_stack: 0, user_data: 0, user_code: 0, tss: core::ptr::null_mut(), cpu: 0 }
+    }
+}
+
+static mut CPU_LOCALS: [CpuLocal; MAX_CPUS] = [CpuLocal::new(); MAX_CPUS];
+
+// Set the stack used by the current CPU when an interrupt or a syscall
+// happens in user mode, which is the kernel stack of the process that will
+// run next.
+pub fn set_kernel_stack(addr: VirtAddr) {
+    // NOTE: The TSS is only read by the CPU during a privilege change so it
+    // can be updated while it is loaded.
     unsafe {
-        CS::set_reg(GDT.1.code);
-        DS::set_reg(GDT.1.data);
-        load_tss(GDT.1.tss);
+        let local = &mut CPU_LOCALS[sys::cpu::id()];
+        (*local.tss).privilege_stack_table[0] = addr;
+        local.kernel_stack = addr.as_u64();
+    }
+}
+
+// Top of the stacks given by the TSS of the current CPU, where interrupts and
+// syscalls are handled.
+pub fn stack_tops() -> [u64; 4] {
+    let tss = unsafe { CPU_LOCALS[sys::cpu::id()].tss };
+    if tss.is_null() {
+        return [0; 4];
     }
+    let tss = unsafe { &*tss };
+    [
+        tss.privilege_stack_table[0].as_u64(),
+        tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize].as_u64(),
+        tss.interrupt_stack_table[PAGE_FAULT_IST_INDEX as usize].as_u64(),
+        tss.interrupt_stack_table[GENERAL_PROTECTION_FAULT_IST_INDEX as usize].as_u64(),
+    ]
+}
+
+fn allocate_stack() -> VirtAddr {
+    let stack = vec![0u8; STACK_SIZE].leak();
+    VirtAddr::from_ptr(stack.as_ptr()) + STACK_SIZE
+}
+
+fn load(cpu: usize, gdt: &'static (GlobalDescriptorTable, Selectors), tss: &'static TaskStateSegment) {
+    gdt.0.load();
+    unsafe {
+        CS::set_reg(gdt.1.code);
+        DS::set_reg(gdt.1.data);
+        load_tss(gdt.1.tss);
+
+        // The kernel GS base is swapped with the GS base by SWAPGS
+        let local = &mut CPU_LOCALS[cpu];
+        local.user_data = gdt.1.user_data.0 as u64;
+        local.user_code = gdt.1.user_code.0 as u64;
+        local.tss = tss as *const TaskStateSegment as *mut TaskStateSegment;
+        local.cpu = cpu as u64;
+        KernelGsBase::write(VirtAddr::from_ptr(local));
+    }
+}
+
+pub fn init() {
+    load(0, &GDT, &TSS);
+}
+
+// Load the tables of an application processor
+pub fn init_ap(cpu: usize) {
+    let mut tss = TaskStateSegment::new();
+    tss.privilege_stack_table[0] = allocate_stack();
+    tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = allocate_stack();
+    tss.interrupt_stack_table[PAGE_FAULT_IST_INDEX as usize] = allocate_stack();
+    tss.interrupt_stack_table[GENERAL_PROTECTION_FAULT_IST_INDEX as usize] = allocate_stack();
+    let tss: &'static TaskStateSegment = Box::leak(Box::new(tss));
+    let gdt: &'static (GlobalDescriptorTable, Selectors) = Box::leak(Box::new(create_gdt(tss)));
+    load(cpu, gdt, tss);
 }
@@ -2,7 +2,12 @@ use crate::sys;
 use lazy_static::lazy_static;
 use spin::Mutex;
 use x86_64::instructions::interrupts;
+use core::sync::atomic::{AtomicU16, Ordering};
 use x86_64::instructions::port::Port;
+use x86_64::registers::control::Cr2;
+use x86_64::registers::model_specific::{Efer, EferFlags, LStar, SFMask, Star};
+use x86_64::registers::rflags::RFlags;
+use x86_64::VirtAddr;
 use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode};
 
 const PIC1: u16 = 0x21;
@@ -10,6 +15,17 @@ const PIC2: u16 = 0xA1;
 
 pub fn init() {
     IDT.load();
+
+    // Enable the SYSCALL instruction, with the interrupts disabled until the
+    // kernel stack is loaded like with the `int 0x80` interrupt gate.
+    let selectors = &sys::gdt::GDT.1;
+    Star::write(selectors.user_code, selectors.user_data, selectors.code, selectors.data).
+        expect("invalid segments for syscall");
+    LStar::write(VirtAddr::new(syscall_entry as u64));
+    SFMask::write(RFlags::INTERRUPT_FLAG | RFlags::DIRECTION_FLAG | RFlags::TRAP_FLAG);
+    unsafe {
+        Efer::update(|flags| *flags |= EferFlags::SYSTEM_CALL_EXTENSIONS);
+    }
 }
 
 // Translate IRQ into system interrupt
@@ -19,6 +35,22 @@ fn interrupt_index(irq: u8) -> u8 {
 
 fn default_irq_handler() {}
 
+// IRQs enabled by the drivers, to be routed when the APIC replaces the PIC
+static ENABLED_IRQS: AtomicU16 = AtomicU16::new(0);
+
+pub fn is_irq_enabled(irq: u8) -> bool {
+    ENABLED_IRQS.load(Ordering::SeqCst) & (1 << irq) != 0
+}
+
+// Acknowledge an IRQ to the controller that delivered it
+fn notify_end_of_interrupt(irq: u8) {
+    if sys::apic::is_enabled() {
+        sys::apic::end_of_interrupt();
+    } else {
+        unsafe { sys::pic::PICS.lock().notify_end_of_interrupt(interrupt_index(irq)) };
+    }
+}
+
 lazy_static! {
     pub static ref IRQ_HANDLERS: Mutex<[fn(); 16]> = Mutex::new([default_irq_handler; 16]);
 
@@ -30,17 +62,28 @@ lazy_static! {
                 set_handler_fn(double_fault_handler).
                 set_stack_index(sys::gdt::DOUBLE_FAULT_IST_INDEX);
             idt.page_fault.
-                set_handler_fn(page_fault_handler).
+                set_handler_fn(core::mem::transmute(wrapped_page_fault_handler as *mut fn())).
                 set_stack_index(sys::gdt::PAGE_FAULT_IST_INDEX);
             idt.general_protection_fault.
-                set_handler_fn(general_protection_fault_handler).
+                set_handler_fn(core::mem::transmute(wrapped_general_protection_fault_handler as *mut fn())).
                 set_stack_index(sys::gdt::GENERAL_PROTECTION_FAULT_IST_INDEX);
+            idt.stack_segment_fault.
+                set_handler_fn(core::mem::transmute(wrapped_stack_segment_fault_handler as *mut fn()));
+            idt.segment_not_present.
+                set_handler_fn(core::mem::transmute(wrapped_segment_not_present_handler as *mut fn()));
+            idt.divide_error.
+                set_handler_fn(core::mem::transmute(wrapped_divide_error_handler as *mut fn()));
+            idt.invalid_opcode.
+                set_handler_fn(core::mem::transmute(wrapped_invalid_opcode_handler as *mut fn()));
             idt[0x80].
                 set_handler_fn(core::mem::transmute(wrapped_syscall_handler as *mut fn())).
-                set_stack_index(sys::gdt::DOUBLE_FAULT_IST_INDEX).
                 set_privilege_level(x86_64::PrivilegeLevel::Ring3);
+            idt[interrupt_index(0) as usize].
+                set_handler_fn(core::mem::transmute(wrapped_irq0_handler as *mut fn()));
+            idt[sys::apic::TIMER_VECTOR as usize].
+                set_handler_fn(core::mem::transmute(wrapped_timer_handler as *mut fn()));
         }
-        idt[interrupt_index(0) as usize].set_handler_fn(irq0_handler);
+        idt[sys::apic::SPURIOUS_VECTOR as usize].set_handler_fn(spurious_handler);
         idt[interrupt_index(1) as usize].set_handler_fn(irq1_handler);
         idt[interrupt_index(2) as usize].set_handler_fn(irq2_handler);
         idt[interrupt_index(3) as usize].set_handler_fn(irq3_handler);
@@ -56,8 +99,6 @@ lazy_static! {
         idt[interrupt_index(13) as usize].set_handler_fn(irq13_handler);
         idt[interrupt_index(14) as usize].set_handler_fn(irq14_handler);
         idt[interrupt_index(15) as usize].set_handler_fn(irq15_handler);
-        idt.stack_segment_fault.set_handler_fn(stack_segment_fault_handler);
-        idt.segment_not_present.set_handler_fn(segment_not_present_handler);
         idt
     };
 }
@@ -67,12 +108,11 @@ macro_rules! irq_handler {
         pub extern "x86-interrupt" fn $handler(_stack_frame: InterruptStackFrame) {
             let handlers = IRQ_HANDLERS.lock();
             handlers[$irq]();
-            unsafe { sys::pic::PICS.lock().notify_end_of_interrupt(interrupt_index($irq)); }
+            notify_end_of_interrupt($irq);
         }
     };
 }
 
-irq_handler!(irq0_handler, 0);
 irq_handler!(irq1_handler, 1);
 irq_handler!(irq2_handler, 2);
 irq_handler!(irq3_handler, 3);
@@ -89,6 +129,9 @@ irq_handler!(irq13_handler, 13);
 irq_handler!(irq14_handler, 14);
 irq_handler!(irq15_handler, 15);
 
+// The spurious interrupts of the APIC must not be acknowledged
+extern "x86-interrupt" fn spurious_handler(_stack_frame: InterruptStackFrame) {}
+
 extern "x86-interrupt" fn breakpoint_handler(stack_frame: InterruptStackFrame) {
     printk!("EXCEPTION: BREAKPOINT\n{:#?}\n", stack_frame);
 }
@@ -97,44 +140,98 @@ extern "x86-interrupt" fn double_fault_handler(stack_frame: InterruptStackFrame,
     panic!("EXCEPTION: DOUBLE FAULT\n{:#?}", stack_frame);
 }
 
-extern "x86-interrupt" fn page_fault_handler(stack_frame: InterruptStackFrame, error_code: PageFaultErrorCode) {
-    let ip = stack_frame.instruction_pointer.as_ptr();
-    let inst: [u8; 8] = unsafe { core::ptr::read(ip) };
-    println!("Code: {:?}", inst);
-    panic!("EXCEPTION: PAGE FAULT\n{:#?}\n{:#?}", stack_frame, error_code);
+// NOTE: The exceptions that can be caused by user code are wrapped like the
+// syscall handler to be able to terminate the faulting process and switch to
+// the next one instead of panicking.
+extern "sysv64" fn page_fault_handler(stack_frame: &mut InterruptStackFrame, regs: &mut Registers, error_code: u64) {
+    let addr = Cr2::read();
+    if is_user_mode(stack_frame) {
+        printk!("Segmentation fault: page fault at {:#x} (rip: {:#x})\n", addr.as_u64(), stack_frame.instruction_pointer.as_u64());
+        kill_faulting_process(stack_frame, regs);
+    } else {
+        let ip = stack_frame.instruction_pointer.as_ptr();
+        let inst: [u8; 8] = unsafe { core::ptr::read(ip) };
+        println!("Code: {:?}", inst);
+        let error_code = PageFaultErrorCode::from_bits_truncate(error_code);
+        panic!("EXCEPTION: PAGE FAULT AT {:#x}\n{:#?}\n{:#?}", addr.as_u64(), stack_frame, error_code);
+    }
+}
+
+extern "sysv64" fn general_protection_fault_handler(stack_frame: &mut InterruptStackFrame, regs: &mut Registers, _error_code: u64) {
+    if is_user_mode(stack_frame) {
+        printk!("Segmentation fault: general protection fault (rip: {:#x})\n", stack_frame.instruction_pointer.as_u64());
+        kill_faulting_process(stack_frame, regs);
+    } else {
+        panic!("EXCEPTION: GENERAL PROTECTION FAULT\n{:#?}", stack_frame);
+    }
+}
+
+extern "sysv64" fn stack_segment_fault_handler(stack_frame: &mut InterruptStackFrame, regs: &mut Registers, _error_code: u64) {
+    if is_user_mode(stack_frame) {
+        printk!("Segmentation fault: stack segment fault (rip: {:#x})\n", stack_frame.instruction_pointer.as_u64());
+        kill_faulting_process(stack_frame, regs);
+    } else {
+        panic!("EXCEPTION: STACK SEGMENT FAULT\n{:#?}", stack_frame);
+    }
+}
+
+extern "sysv64" fn segment_not_present_handler(stack_frame: &mut InterruptStackFrame, regs: &mut Registers, _error_code: u64) {
+    if is_user_mode(stack_frame) {
+        printk!("Segmentation fault: segment not present (rip: {:#x})\n", stack_frame.instruction_pointer.as_u64());
+        kill_faulting_process(stack_frame, regs);
+    } else {
+        panic!("EXCEPTION: SEGMENT NOT PRESENT\n{:#?}", stack_frame);
+    }
+}
+
+extern "sysv64" fn divide_error_handler(stack_frame: &mut InterruptStackFrame, regs: &mut Registers) {
+    if is_user_mode(stack_frame) {
+        printk!("Floating point exception: divide error (rip: {:#x})\n", stack_frame.instruction_pointer.as_u64());
+        kill_faulting_process(stack_frame, regs);
+    } else {
+        panic!("EXCEPTION: DIVIDE ERROR\n{:#?}", stack_frame);
+    }
 }
 
-extern "x86-interrupt" fn general_protection_fault_handler(stack_frame: InterruptStackFrame, _error_code: u64) {
-    panic!("EXCEPTION: GENERAL PROTECTION FAULT\n{:#?}", stack_frame);
+extern "sysv64" fn invalid_opcode_handler(stack_frame: &mut InterruptStackFrame, regs: &mut Registers) {
+    if is_user_mode(stack_frame) {
+        printk!("Illegal instruction: invalid opcode (rip: {:#x})\n", stack_frame.instruction_pointer.as_u64());
+        kill_faulting_process(stack_frame, regs);
+    } else {
+        panic!("EXCEPTION: INVALID OPCODE\n{:#?}", stack_frame);
+    }
 }
 
-extern "x86-interrupt" fn stack_segment_fault_handler(stack_frame: InterruptStackFrame, _error_code: u64) {
-    panic!("EXCEPTION: STACK SEGMENT FAULT\n{:#?}", stack_frame);
+fn is_user_mode(stack_frame: &InterruptStackFrame) -> bool {
+    stack_frame.code_segment & 3 == 3
 }
 
-extern "x86-interrupt" fn segment_not_present_handler(stack_frame: InterruptStackFrame, _error_code: u64) {
-    panic!("EXCEPTION: SEGMENT NOT PRESENT\n{:#?}", stack_frame);
+// Terminate the process that caused an exception and replace its context with
+// the context of the next process.
+fn kill_faulting_process(stack_frame: &mut InterruptStackFrame, regs: &mut Registers) {
+    sys::process::exit(sys::process::FAULT_CODE);
+    sys::process::switch(stack_frame, regs);
 }
 
 // See: https://github.com/xfoxfu/rust-xos/blob/8a07a69ef/kernel/src/interrupts/handlers.rs#L92
 #[repr(align(8), C)]
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Copy, Default)]
 pub struct Registers {
-    r15: usize,
-    r14: usize,
-    r13: usize,
-    r12: usize,
-    r11: usize,
-    r10: usize,
-    r9: usize,
-    r8: usize,
-    rdi: usize,
-    rsi: usize,
-    rdx: usize,
-    rcx: usize,
-    rbx: usize,
-    rax: usize,
-    rbp: usize,
+    pub r15: usize,
+    pub r14: usize,
+    pub r13: usize,
+    pub r12: usize,
+    pub r11: usize,
+    pub r10: usize,
+    pub r9: usize,
+    pub r8: usize,
+    pub rdi: usize,
+    pub rsi: usize,
+    pub rdx: usize,
+    pub rcx: usize,
+    pub rbx: usize,
+    pub rax: usize,
+    pub rbp: usize,
 }
 
 // See: https://github.com/xfoxfu/rust-xos/blob/8a07a69ef/kernel/src/interrupts/handlers.rs#L112
@@ -185,19 +282,187 @@ macro_rules! wrap {
     };
 }
 
+// Same as `wrap` for the exceptions pushing an error code after the interrupt
+// frame, the code is given as a third argument to the handler and removed
+// from the stack before returning.
+macro_rules! wrap_with_error_code {
+    ($fn: ident => $w:ident) => {
+        #[naked]
+        pub unsafe extern "sysv64" fn $w() {
+            asm!(
+                "push rbp",
+                "push rax",
+                "push rbx",
+                "push rcx",
+                "push rdx",
+                "push rsi",
+                "push rdi",
+                "push r8",
+                "push r9",
+                "push r10",
+                "push r11",
+                "push r12",
+                "push r13",
+                "push r14",
+                "push r15",
+                "mov rsi, rsp", // Arg #2: register list
+                "mov rdi, rsp", // Arg #1: interupt frame
+                "add rdi, 16 * 8",
+                "mov rdx, [rsp + 15 * 8]", // Arg #3: error code
+                "sub rsp, 8", // Align the stack on 16 bytes
+                "call {}",
+                "add rsp, 8",
+                "pop r15",
+                "pop r14",
+                "pop r13",
+                "pop r12",
+                "pop r11",
+                "pop r10",
+                "pop r9",
+                "pop r8",
+                "pop rdi",
+                "pop rsi",
+                "pop rdx",
+                "pop rcx",
+                "pop rbx",
+                "pop rax",
+                "pop rbp",
+                "add rsp, 8", // Error code
+                "iretq",
+                sym $fn,
+                options(noreturn)
+            );
+        }
+    };
+}
+
 wrap!(syscall_handler => wrapped_syscall_handler);
+wrap!(irq0_handler => wrapped_irq0_handler);
+wrap!(timer_handler => wrapped_timer_handler);
+wrap!(divide_error_handler => wrapped_divide_error_handler);
+wrap!(invalid_opcode_handler => wrapped_invalid_opcode_handler);
+wrap_with_error_code!(page_fault_handler => wrapped_page_fault_handler);
+wrap_with_error_code!(general_protection_fault_handler => wrapped_general_protection_fault_handler);
+wrap_with_error_code!(stack_segment_fault_handler => wrapped_stack_segment_fault_handler);
+wrap_with_error_code!(segment_not_present_handler => wrapped_segment_not_present_handler);
 
 // NOTE: We can't use "x86-interrupt" for syscall_handler because we need to
 // return a result in the RAX register and it will be overwritten when the
 // context of the caller is restored.
-extern "sysv64" fn syscall_handler(_stack_frame: &mut InterruptStackFrame, regs: &mut Registers) {
-    // The registers order follow the System V ABI convention
+extern "sysv64" fn syscall_handler(stack_frame: &mut InterruptStackFrame, regs: &mut Registers) {
+    dispatch_syscall(stack_frame, regs);
+}
+
+fn dispatch_syscall(stack_frame: &mut InterruptStackFrame, regs: &mut Registers) {
+    // The registers order follow the System V ABI convention, with R10
+    // instead of RCX for the fourth argument because SYSCALL overwrites RCX
+    // with the return address, like on Linux.
     let n    = regs.rax;
     let arg1 = regs.rdi;
     let arg2 = regs.rsi;
     let arg3 = regs.rdx;
-    regs.rax = sys::syscall::dispatcher(n, arg1, arg2, arg3);
-    unsafe { sys::pic::PICS.lock().notify_end_of_interrupt(0x80) };
+    let arg4 = regs.r10;
+    let res = sys::syscall::dispatcher(n, arg1, arg2, arg3, arg4);
+    if sys::process::has_exited() {
+        // Replace the context of the process that just exited with the
+        // context of the next process instead of returning to it.
+        sys::process::switch(stack_frame, regs);
+    } else if n == sys::syscall::number::SIGRETURN && res == 0 {
+        // Resume the context interrupted by a signal handler
+        sys::process::restore_signal_context(stack_frame, regs);
+    } else {
+        regs.rax = res;
+    }
+    sys::process::handle_signals(stack_frame, regs);
+}
+
+// Handler of the SYSCALL instruction called with the same frame as an
+// interrupt. It returns 0 if the context can be restored with SYSRET, which
+// uses RCX and R11 for the instruction pointer and the flags, or 1 if IRETQ
+// is needed because the context has been replaced.
+extern "sysv64" fn fast_syscall_handler(stack_frame: &mut InterruptStackFrame, regs: &mut Registers) -> usize {
+    dispatch_syscall(stack_frame, regs);
+    let is_user = stack_frame.code_segment & 3 == 3;
+    let is_same_rip = stack_frame.instruction_pointer.as_u64() == regs.rcx as u64;
+    let is_same_flags = stack_frame.cpu_flags == regs.r11 as u64;
+    if is_user && is_same_rip && is_same_flags { 0 } else { 1 }
+}
+
+// Entry point of the SYSCALL instruction, that doesn't switch to the kernel
+// stack, so we do it with the CPU local data given by SWAPGS before building
+// the same frame as an interrupt.
+#[naked]
+unsafe extern "sysv64" fn syscall_entry() {
+    asm!(
+        "swapgs",
+        "mov gs:[0x08], rsp", // Save the user stack
+        "mov rsp, gs:[0x00]", // Load the kernel stack
+        "push qword ptr gs:[0x10]", // SS
+        "push qword ptr gs:[0x08]", // RSP
+        "push r11", // RFLAGS
+        "push qword ptr gs:[0x18]", // CS
+        "push rcx", // RIP
+        "swapgs",
+        "push rbp",
+        "push rax",
+        "push rbx",
+        "push rcx",
+        "push rdx",
+        "push rsi",
+        "push rdi",
+        "push r8",
+        "push r9",
+        "push r10",
+        "push r11",
+        "push r12",
+        "push r13",
+        "push r14",
+        "push r15",
+        "mov rsi, rsp", // Arg #2: register list
+        "mov rdi, rsp", // Arg #1: interupt frame
+        "add rdi, 15 * 8",
+        "call {}",
+        "test rax, rax", // The flags are not modified by POP
+        "pop r15",
+        "pop r14",
+        "pop r13",
+        "pop r12",
+        "pop r11",
+        "pop r10",
+        "pop r9",
+        "pop r8",
+        "pop rdi",
+        "pop rsi",
+        "pop rdx",
+        "pop rcx",
+        "pop rbx",
+        "pop rax",
+        "pop rbp",
+        "jnz 2f",
+        "mov rsp, [rsp + 3 * 8]", // Restore the user stack from the frame
+        "sysretq",
+        "2:",
+        "iretq",
+        sym fast_syscall_handler,
+        options(noreturn)
+    );
+}
+
+// NOTE: The PIT interrupt handler is wrapped like the syscall handler to give
+// the scheduler access to the context of the interrupted process.
+extern "sysv64" fn irq0_handler(stack_frame: &mut InterruptStackFrame, regs: &mut Registers) {
+    IRQ_HANDLERS.lock()[0]();
+    notify_end_of_interrupt(0);
+    if !sys::apic::is_enabled() { // The LAPIC timer drives the scheduler otherwise
+        sys::process::schedule(stack_frame, regs);
+        sys::process::handle_signals(stack_frame, regs);
+    }
+}
+
+extern "sysv64" fn timer_handler(stack_frame: &mut InterruptStackFrame, regs: &mut Registers) {
+    sys::apic::end_of_interrupt();
+    sys::process::schedule(stack_frame, regs);
+    sys::process::handle_signals(stack_frame, regs);
 }
 
 pub fn set_irq_handler(irq: u8, handler: fn()) {
@@ -210,6 +475,10 @@ pub fn set_irq_handler(irq: u8, handler: fn()) {
 }
 
 pub fn set_irq_mask(irq: u8) {
+    ENABLED_IRQS.fetch_and(!(1 << irq), Ordering::SeqCst);
+    if sys::apic::is_enabled() {
+        return sys::apic::set_irq_mask(irq);
+    }
     let mut port: Port<u8> = Port::new(if irq < 8 { PIC1 } else { PIC2 });
     unsafe {
         let value = port.read() | (1 << (if irq < 8 { irq } else { irq - 8 }));
@@ -218,6 +487,10 @@ pub fn set_irq_mask(irq: u8) {
 }
 
 pub fn clear_irq_mask(irq: u8) {
+    ENABLED_IRQS.fetch_or(1 << irq, Ordering::SeqCst);
+    if sys::apic::is_enabled() {
+        return sys::apic::clear_irq_mask(irq);
+    }
     let mut port: Port<u8> = Port::new(if irq < 8 { PIC1 } else { PIC2 });
     unsafe {
         let value = port.read() & !(1 << if irq < 8 { irq } else { irq - 8 });
@@ -0,0 +1,217 @@
+use crate::api::console::Style;
+use crate::sys;
+use crate::sys::fs::FileIO;
+use alloc::format;
+use alloc::string::String;
+use core::fmt;
+use core::fmt::Write;
+use core::str::FromStr;
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+// The log is kept in a fixed ring buffer without any allocation, so it can be
+// used before the heap is ready and by interrupt handlers.
+const MAX_ENTRIES: usize = 256;
+const MAX_MESSAGE_LEN: usize = 120;
+
+static BUFFER: Mutex<Buffer> = Mutex::new(Buffer::new());
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Error = 0,
+    Warning = 1,
+    Info = 2,
+    Debug = 3,
+}
+
+impl Level {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Level::Error => "ERROR",
+            Level::Warning => "WARN",
+            Level::Info => "INFO",
+            Level::Debug => "DEBUG",
+        }
+    }
+
+    pub fn color(&self) -> &'static str {
+        match self {
+            Level::Error => "LightRed",
+            Level::Warning => "Yellow",
+            Level::Info => "LightGreen",
+            Level::Debug => "LightGray",
+        }
+    }
+}
+
+impl FromStr for Level {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "error" => Ok(Level::Error),
+            "warn" | "warning" => Ok(Level::Warning),
+            "info" => Ok(Level::Info),
+            "debug" => Ok(Level::Debug),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Entry {
+    id: u64,
+    time: f64,
+    level: Level,
+    len: usize,
+    message: [u8; MAX_MESSAGE_LEN],
+}
+
+impl Entry {
+    const fn empty() -> Self {
+        Self { id: 0, time: 0.0, level: Level::Info, len: 0, message: [0; MAX_MESSAGE_LEN] }
+    }
+
+    fn message(&self) -> String {
+        String::from_utf8_lossy(&self.message[0..self.len]).into()
+    }
+
+    fn line(&self) -> String {
+        format!("[{:.6}] {} {}\n", self.time, self.level.as_str(), self.message())
+    }
+}
+
+// Truncate the formatted message to the size of an entry
+impl fmt::Write for Entry {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            let n = c.len_utf8();
+            if self.len + n > MAX_MESSAGE_LEN {
+                break;
+            }
+            c.encode_utf8(&mut self.message[self.len..]);
+            self.len += n;
+        }
+        Ok(())
+    }
+}
+
+struct Buffer {
+    entries: [Entry; MAX_ENTRIES],
+    next_id: u64,
+}
+
+impl Buffer {
+    const fn new() -> Self {
+        Self { entries: [Entry::empty(); MAX_ENTRIES], next_id: 0 }
+    }
+
+    // Overwrite the oldest entry
+    fn push(&mut self, time: f64, level: Level, args: fmt::Arguments) {
+        let mut entry = Entry { id: self.next_id, time, level, ..Entry::empty() };
+        entry.write_fmt(args).ok();
+        while entry.len > 0 && entry.message[entry.len - 1] == b'\n' {
+            entry.len -= 1;
+        }
+        self.entries[(self.next_id as usize) % MAX_ENTRIES] = entry;
+        self.next_id += 1;
+    }
+
+    // Copy the lines of the entries following the given position into the
+    // buffer, skipping the entries that have been overwritten, and return the
+    // number of bytes copied.
+    fn read(&self, pos: &mut Position, buf: &mut [u8]) -> usize {
+        let oldest_id = self.next_id.saturating_sub(MAX_ENTRIES as u64);
+        if pos.id < oldest_id {
+            pos.id = oldest_id;
+            pos.offset = 0;
+        }
+        let mut n = 0;
+        while pos.id < self.next_id && n < buf.len() {
+            let line = self.entries[(pos.id as usize) % MAX_ENTRIES].line();
+            let bytes = &line.as_bytes()[pos.offset..];
+            let m = bytes.len().min(buf.len() - n);
+            buf[n..(n + m)].copy_from_slice(&bytes[0..m]);
+            n += m;
+            if m < bytes.len() {
+                pos.offset += m;
+            } else {
+                pos.id += 1;
+                pos.offset = 0;
+            }
+        }
+        n
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Position {
+    id: u64,
+    offset: usize,
+}
+
+// Print a message on the console and keep it in the log
+pub fn write(level: Level, args: fmt::Arguments) {
+    let time = sys::clock::uptime();
+    let csi_color = Style::color(level.color());
+    let csi_reset = Style::reset();
+    sys::console::print_fmt(format_args!("{}[{:.6}]{} ", csi_color, time, csi_reset));
+    sys::console::print_fmt(args);
+    interrupts::without_interrupts(|| {
+        BUFFER.lock().push(time, level, args);
+    });
+}
+
+// Device reading the lines of the log from the oldest entry still in the
+// buffer, or writing a message at the info level.
+#[derive(Debug, Clone)]
+pub struct Log {
+    pos: Position,
+}
+
+impl Log {
+    pub fn new() -> Self {
+        Self { pos: Position::default() }
+    }
+}
+
+impl FileIO for Log {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, ()> {
+        let pos = &mut self.pos;
+        Ok(interrupts::without_interrupts(|| BUFFER.lock().read(pos, buf)))
+    }
+
+    fn write(&mut self, buf: &[u8]) -> Result<usize, ()> {
+        let message = String::from_utf8_lossy(buf);
+        let message = message.trim_end();
+        write(Level::Info, format_args!("{}\n", message));
+        Ok(buf.len())
+    }
+}
+
+#[test_case]
+fn test_log() {
+    let mut buffer = Buffer::new();
+    buffer.push(1.0, Level::Warning, format_args!("ATA {}:{} timeout\n", 0, 1));
+    buffer.push(2.0, Level::Info, format_args!("{}", "x".repeat(2 * MAX_MESSAGE_LEN)));
+    assert_eq!(buffer.entries[0].message(), "ATA 0:1 timeout");
+    assert_eq!(buffer.entries[1].len, MAX_MESSAGE_LEN);
+
+    // Read a line in two steps
+    let mut pos = Position::default();
+    let mut buf = [0; 16];
+    assert_eq!(buffer.read(&mut pos, &mut buf[0..10]), 10);
+    assert_eq!(buffer.read(&mut pos, &mut buf[10..16]), 6);
+    assert_eq!(&buf, b"[1.000000] WARN ");
+
+    // Skip the overwritten entries
+    for _ in 0..MAX_ENTRIES {
+        buffer.push(3.0, Level::Error, format_args!("Error"));
+    }
+    let mut buf = [0; 25];
+    assert_eq!(buffer.read(&mut pos, &mut buf), 25);
+    assert_eq!(&buf, b"[3.000000] ERROR Error\n[3");
+
+    assert_eq!("warning".parse(), Ok(Level::Warning));
+    assert!(Level::Error < Level::Debug);
+}
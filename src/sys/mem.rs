@@ -1,13 +1,18 @@
 use crate::sys;
 use bootloader::bootinfo::{BootInfo, MemoryMap, MemoryRegionType};
+use spin::{Mutex, MutexGuard, Once};
 use x86_64::instructions::interrupts;
-use x86_64::structures::paging::{FrameAllocator, OffsetPageTable, PageTable, PhysFrame, Size4KiB, Translate};
+use x86_64::structures::paging::{FrameAllocator, FrameDeallocator, Mapper, OffsetPageTable, Page, PageTable, PageTableFlags, PhysFrame, Size4KiB, Translate};
 use x86_64::{PhysAddr, VirtAddr};
 
 // NOTE: mutable but changed only once during initialization
 pub static mut PHYS_MEM_OFFSET: u64 = 0;
 pub static mut MEMORY_MAP: Option<&MemoryMap> = None;
 
+const FRAME_SIZE: u64 = 4096;
+
+static FRAME_ALLOCATOR: Once<Mutex<BitmapFrameAllocator>> = Once::new();
+
 pub fn init(boot_info: &'static BootInfo) {
     interrupts::without_interrupts(|| {
         let mut memory_size = 0;
@@ -23,9 +28,11 @@ pub fn init(boot_info: &'static BootInfo) {
         unsafe { MEMORY_MAP.replace(&boot_info.memory_map) };
 
         let mut mapper = unsafe { mapper(VirtAddr::new(PHYS_MEM_OFFSET)) };
-        let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+        FRAME_ALLOCATOR.call_once(|| {
+            Mutex::new(unsafe { BitmapFrameAllocator::init(&boot_info.memory_map) })
+        });
 
-        sys::allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+        sys::allocator::init_heap(&mut mapper, &mut *frame_allocator()).expect("heap initialization failed");
     });
 }
 
@@ -38,6 +45,48 @@ pub fn virt_to_phys(addr: VirtAddr) -> Option<PhysAddr> {
     mapper.translate_addr(addr)
 }
 
+// Give access to the registers of a device with an uncached page if the
+// physical address is not already mapped.
+pub fn map_registers(addr: u64) -> u64 {
+    let virt_addr = phys_to_virt(PhysAddr::new(addr));
+    if virt_to_phys(virt_addr).is_none() {
+        let page = Page::<Size4KiB>::containing_address(virt_addr);
+        let frame = PhysFrame::containing_address(PhysAddr::new(addr));
+        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_CACHE;
+        let mut mapper = unsafe { mapper(VirtAddr::new(PHYS_MEM_OFFSET)) };
+        let mut frame_allocator = frame_allocator();
+        if let Ok(mapping) = unsafe { mapper.map_to(page, frame, flags, &mut *frame_allocator) } {
+            mapping.flush();
+        }
+    }
+    virt_addr.as_u64()
+}
+
+pub fn frame_allocator() -> MutexGuard<'static, BitmapFrameAllocator> {
+    FRAME_ALLOCATOR.get().expect("frame allocator not initialized").lock()
+}
+
+pub fn allocate_frame() -> Option<PhysFrame> {
+    frame_allocator().allocate_frame()
+}
+
+pub fn deallocate_frame(frame: PhysFrame) {
+    unsafe { frame_allocator().deallocate_frame(frame) }
+}
+
+// Size of the usable physical memory
+pub fn memory_size() -> usize {
+    frame_allocator().frames_count * FRAME_SIZE as usize
+}
+
+pub fn memory_used() -> usize {
+    frame_allocator().frames_used * FRAME_SIZE as usize
+}
+
+pub fn memory_free() -> usize {
+    memory_size() - memory_used()
+}
+
 pub unsafe fn mapper(physical_memory_offset: VirtAddr) -> OffsetPageTable<'static> {
     let level_4_table = active_level_4_table(physical_memory_offset);
     OffsetPageTable::new(level_4_table, physical_memory_offset)
@@ -55,31 +104,139 @@ unsafe fn active_level_4_table(physical_memory_offset: VirtAddr) -> &'static mut
     &mut *page_table_ptr // unsafe
 }
 
-pub struct BootInfoFrameAllocator {
-    memory_map: &'static MemoryMap,
-    next: usize,
+// Give access to a page table that is not necessarily the active one
+pub unsafe fn page_table(frame: PhysFrame) -> &'static mut PageTable {
+    let virt = phys_to_virt(frame.start_address());
+    let page_table_ptr: *mut PageTable = virt.as_mut_ptr();
+
+    &mut *page_table_ptr // unsafe
+}
+
+// The frame allocator keeps track of the physical memory with a bitmap having
+// one bit per frame, set when the frame is not free. The bitmap is itself
+// stored at the beginning of the first usable region big enough to hold it.
+pub struct BitmapFrameAllocator {
+    bitmap: &'static mut [u64],
+    frames_count: usize,
+    frames_used: usize,
+    next: usize, // Index of the first frame that might be free
 }
 
-impl BootInfoFrameAllocator {
+impl BitmapFrameAllocator {
     pub unsafe fn init(memory_map: &'static MemoryMap) -> Self {
-        BootInfoFrameAllocator { memory_map, next: 0 }
+        let usable_regions = || memory_map.iter().filter(|r| r.region_type == MemoryRegionType::Usable);
+        let max_addr = usable_regions().map(|r| r.range.end_addr()).max().unwrap_or(0);
+        let len = ((max_addr / FRAME_SIZE) as usize + 63) / 64;
+        let bitmap_size = (len * 8) as u64;
+        let region = usable_regions().find(|r| {
+            r.range.end_addr() - r.range.start_addr() >= bitmap_size
+        }).expect("could not find memory for the frame bitmap");
+        let bitmap_addr = PhysAddr::new(region.range.start_addr());
+        let ptr = phys_to_virt(bitmap_addr).as_mut_ptr::<u64>();
+        let bitmap = core::slice::from_raw_parts_mut(ptr, len);
+        for word in bitmap.iter_mut() {
+            *word = u64::MAX;
+        }
+
+        let mut allocator = Self { bitmap, frames_count: 0, frames_used: 0, next: 0 };
+        for region in usable_regions() {
+            let start = (region.range.start_addr() / FRAME_SIZE) as usize;
+            let end = (region.range.end_addr() / FRAME_SIZE) as usize;
+            for i in start..end {
+                allocator.set_free(i);
+                allocator.frames_count += 1;
+            }
+        }
+
+        let start = (bitmap_addr.as_u64() / FRAME_SIZE) as usize;
+        let end = start + ((bitmap_size + FRAME_SIZE - 1) / FRAME_SIZE) as usize;
+        for i in start..end {
+            allocator.set_used(i);
+            allocator.frames_used += 1;
+        }
+        allocator.update_next();
+
+        allocator
+    }
+
+    fn is_used(&self, i: usize) -> bool {
+        self.bitmap[i / 64] & (1 << (i % 64)) != 0
+    }
+
+    fn set_used(&mut self, i: usize) {
+        self.bitmap[i / 64] |= 1 << (i % 64);
+    }
+
+    fn set_free(&mut self, i: usize) {
+        self.bitmap[i / 64] &= !(1 << (i % 64));
     }
 
-    fn usable_frames(&self) -> impl Iterator<Item = PhysFrame> {
-        let regions = self.memory_map.iter();
-        let usable_regions = regions.filter(|r| r.region_type == MemoryRegionType::Usable);
-        let addr_ranges = usable_regions.map(|r| r.range.start_addr()..r.range.end_addr());
-        let frame_addresses = addr_ranges.flat_map(|r| r.step_by(4096));
-        frame_addresses.map(|addr| PhysFrame::containing_address(PhysAddr::new(addr)))
+    fn update_next(&mut self) {
+        let n = self.bitmap.len() * 64;
+        while self.next < n && self.is_used(self.next) {
+            self.next += 1;
+        }
+    }
+
+    // Allocate physically contiguous frames and return the first one
+    pub fn allocate_frames(&mut self, count: usize) -> Option<PhysFrame> {
+        let n = self.bitmap.len() * 64;
+        let mut start = self.next;
+        while start + count <= n {
+            match (start..(start + count)).find(|&i| self.is_used(i)) {
+                Some(i) => {
+                    start = i + 1;
+                }
+                None => {
+                    for i in start..(start + count) {
+                        self.set_used(i);
+                    }
+                    self.frames_used += count;
+                    self.update_next();
+                    let addr = PhysAddr::new(start as u64 * FRAME_SIZE);
+                    return Some(PhysFrame::containing_address(addr));
+                }
+            }
+        }
+        None
+    }
+
+    // Allocate a frame below the given address, for the code that must run
+    // in the first MB of memory in real mode
+    pub fn allocate_frame_below(&mut self, addr: PhysAddr) -> Option<PhysFrame> {
+        let n = ((addr.as_u64() / FRAME_SIZE) as usize).min(self.bitmap.len() * 64);
+        let i = (self.next..n).find(|&i| !self.is_used(i))?;
+        self.set_used(i);
+        self.frames_used += 1;
+        self.update_next();
+        Some(PhysFrame::containing_address(PhysAddr::new(i as u64 * FRAME_SIZE)))
     }
 }
 
-unsafe impl FrameAllocator<Size4KiB> for BootInfoFrameAllocator {
+unsafe impl FrameAllocator<Size4KiB> for BitmapFrameAllocator {
     fn allocate_frame(&mut self) -> Option<PhysFrame> {
-        // FIXME: creating an iterator for each allocation is very slow if
-        // the heap is larger than a few megabytes.
-        let frame = self.usable_frames().nth(self.next);
-        self.next += 1;
-        frame
+        self.allocate_frames(1)
     }
 }
+
+impl FrameDeallocator<Size4KiB> for BitmapFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame) {
+        let i = (frame.start_address().as_u64() / FRAME_SIZE) as usize;
+        if self.is_used(i) {
+            self.set_free(i);
+            self.frames_used -= 1;
+            self.next = self.next.min(i);
+        }
+    }
+}
+
+#[test_case]
+fn test_frame_allocator() {
+    let used = memory_used();
+    let frame = allocate_frame().unwrap();
+    assert_eq!(memory_used(), used + FRAME_SIZE as usize);
+    deallocate_frame(frame);
+    assert_eq!(memory_used(), used);
+    assert_eq!(allocate_frame(), Some(frame));
+    deallocate_frame(frame);
+}
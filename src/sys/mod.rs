@@ -9,33 +9,44 @@ macro_rules! printk {
 macro_rules! log {
     ($($arg:tt)*) => ({
         if !cfg!(test) {
-            let uptime = $crate::sys::clock::uptime();
-            let csi_color = $crate::api::console::Style::color("LightGreen");
-            let csi_reset = $crate::api::console::Style::reset();
-            $crate::sys::console::print_fmt(format_args!("{}[{:.6}]{} ", csi_color, uptime, csi_reset));
-            $crate::sys::console::print_fmt(format_args!($($arg)*));
+            $crate::sys::log::write($crate::sys::log::Level::Info, format_args!($($arg)*));
+        }
+    });
+}
+
+#[macro_export]
+macro_rules! warning {
+    ($($arg:tt)*) => ({
+        if !cfg!(test) {
+            $crate::sys::log::write($crate::sys::log::Level::Warning, format_args!($($arg)*));
         }
     });
 }
 
 pub mod acpi;
 pub mod allocator;
+pub mod apic;
 pub mod ata;
+pub mod backtrace;
 pub mod clock;
 pub mod cmos;
 pub mod console;
 pub mod cpu;
+pub mod fpu;
 pub mod fs;
 pub mod gdt;
 pub mod idt;
 pub mod keyboard;
+pub mod log;
 pub mod mem;
 pub mod net;
 pub mod pci;
 pub mod pic;
+pub mod pipe;
 pub mod process;
 pub mod random;
 pub mod serial;
+pub mod smp;
 pub mod syscall;
 pub mod time;
 pub mod vga;
@@ -1,6 +1,8 @@
+use crate::sys;
 use alloc::sync::Arc;
 use core::sync::atomic::{AtomicU64, Ordering};
 use lazy_static::lazy_static;
+use smoltcp::time::Instant;
 use spin::Mutex;
 
 // TODO: Support dyn EthernetInterface
@@ -32,6 +34,11 @@ pub fn init() {
     pcnet::init();
 }
 
+// Timestamp given to the network stack, from the monotonic clock
+pub fn time() -> Instant {
+    Instant::from_millis((sys::clock::monotonic() / 1_000_000) as i64)
+}
+
 struct InnerStats {
     rx_bytes_count: AtomicU64,
     tx_bytes_count: AtomicU64,
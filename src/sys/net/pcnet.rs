@@ -39,7 +39,7 @@ const DE_STP:  usize = 1;
 //const DE_CRC:  usize = 3;
 //const DE_OFLO: usize = 4;
 //const DE_FRAM: usize = 5;
-//const DE_ERR:  usize = 6;
+const DE_ERR:  usize = 6;
 const DE_OWN:  usize = 7;
 
 #[derive(Clone)]
@@ -292,6 +292,9 @@ impl<'a> Device<'a> for PCNET {
 
             let rmd1 = self.rx_des[rx_id * DE_LEN + 7];
             let end_of_packet = rmd1.get_bit(DE_ENP);
+            if rmd1.get_bit(DE_ERR) {
+                warning!("NET PCNET Received a packet with errors\n");
+            }
 
             /*
             let start_of_packet = rmd1.get_bit(DE_STP);
@@ -5,6 +5,7 @@ use alloc::collections::BTreeMap;
 use alloc::vec::Vec;
 use array_macro::array;
 use core::convert::TryInto;
+use core::sync::atomic::{AtomicU16, Ordering};
 use smoltcp::iface::{EthernetInterfaceBuilder, NeighborCache, Routes};
 use smoltcp::phy;
 use smoltcp::phy::{Device, DeviceCapabilities};
@@ -245,6 +246,7 @@ impl<'a> Device<'a> for RTL8139 {
             //printk!("Header: {:#04X}\n", header);
         }
         if header & ROK != ROK {
+            warning!("NET RTL8139 Received a bad packet\n");
             unsafe { self.ports.capr.write(cbr) };
             return None;
         }
@@ -385,16 +387,20 @@ pub fn init() {
             *sys::net::IFACE.lock() = Some(iface);
         }
 
-        //let irq = pci_device.interrupt_line;
-        //sys::idt::set_irq_handler(irq, interrupt_handler);
+        ISR_ADDR.store(io_base + 0x3E, Ordering::SeqCst);
+        let irq = pci_device.interrupt_line;
+        if irq < 16 {
+            sys::idt::set_irq_handler(irq, interrupt_handler);
+        }
     }
 }
 
+// Address of the interrupt status register, used without locking the
+// interface because the interrupt line is level triggered and must be
+// cleared even when the network stack is polling the device.
+static ISR_ADDR: AtomicU16 = AtomicU16::new(0);
+
 pub fn interrupt_handler() {
-    printk!("RTL8139 interrupt!\n");
-    if let Some(mut guard) = sys::net::IFACE.try_lock() {
-        if let Some(ref mut iface) = *guard {
-            unsafe { iface.device_mut().ports.isr.write(0xffff) } // Clear the interrupt
-        }
-    }
+    let mut isr: Port<u16> = Port::new(ISR_ADDR.load(Ordering::SeqCst));
+    unsafe { isr.write(0xffff) } // Clear the interrupt
 }
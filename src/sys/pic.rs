@@ -1,5 +1,6 @@
 use pic8259::ChainedPics;
 use spin::Mutex;
+use x86_64::instructions::port::Port;
 
 pub const PIC_1_OFFSET: u8 = 32;
 pub const PIC_2_OFFSET: u8 = PIC_1_OFFSET + 8;
@@ -12,3 +13,13 @@ pub fn init() {
     }
     x86_64::instructions::interrupts::enable();
 }
+
+// Mask every IRQ when the APIC takes over
+pub fn disable() {
+    let mut pic1: Port<u8> = Port::new(0x21);
+    let mut pic2: Port<u8> = Port::new(0xA1);
+    unsafe {
+        pic1.write(0xFF);
+        pic2.write(0xFF);
+    }
+}
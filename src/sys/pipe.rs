@@ -0,0 +1,140 @@
+use crate::sys;
+use crate::sys::fs::FileIO;
+use alloc::collections::vec_deque::VecDeque;
+use alloc::sync::Arc;
+use spin::Mutex;
+
+const PIPE_SIZE: usize = 4096;
+
+#[derive(Debug)]
+struct PipeBuffer {
+    data: VecDeque<u8>,
+    readers: usize,
+    writers: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PipeEnd {
+    Read,
+    Write,
+}
+
+// One end of a pipe. The ends are counted when they are cloned, for example
+// when a handle is duplicated or inherited by a child process, to let the
+// other side know when nobody is listening or talking anymore.
+#[derive(Debug)]
+pub struct Pipe {
+    buffer: Arc<Mutex<PipeBuffer>>,
+    end: PipeEnd,
+}
+
+impl Pipe {
+    // Create a new pipe and return its read end and its write end
+    pub fn new() -> (Self, Self) {
+        let buffer = Arc::new(Mutex::new(PipeBuffer {
+            data: VecDeque::with_capacity(PIPE_SIZE),
+            readers: 1,
+            writers: 1,
+        }));
+        let reader = Self { buffer: buffer.clone(), end: PipeEnd::Read };
+        let writer = Self { buffer, end: PipeEnd::Write };
+        (reader, writer)
+    }
+}
+
+impl Clone for Pipe {
+    fn clone(&self) -> Self {
+        let mut buffer = self.buffer.lock();
+        match self.end {
+            PipeEnd::Read => buffer.readers += 1,
+            PipeEnd::Write => buffer.writers += 1,
+        }
+        Self { buffer: self.buffer.clone(), end: self.end }
+    }
+}
+
+impl Drop for Pipe {
+    fn drop(&mut self) {
+        let mut buffer = self.buffer.lock();
+        match self.end {
+            PipeEnd::Read => buffer.readers -= 1,
+            PipeEnd::Write => buffer.writers -= 1,
+        }
+    }
+}
+
+impl FileIO for Pipe {
+    // Wait until some data is available and read it, or return 0 when the
+    // pipe is empty and the write end has been closed.
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, ()> {
+        if self.end != PipeEnd::Read {
+            return Err(());
+        }
+        loop {
+            {
+                let mut buffer = self.buffer.lock();
+                if !buffer.data.is_empty() || buffer.writers == 0 || buf.is_empty() {
+                    let n = buf.len().min(buffer.data.len());
+                    for (i, byte) in buffer.data.drain(0..n).enumerate() {
+                        buf[i] = byte;
+                    }
+                    return Ok(n);
+                }
+            }
+            if sys::process::has_pending_signal() {
+                return Err(());
+            }
+            sys::time::halt_preemptible(); // Let the writer run
+        }
+    }
+
+    // Wait until there is enough space for all the data, unless the read end
+    // is closed in the meantime.
+    fn write(&mut self, buf: &[u8]) -> Result<usize, ()> {
+        if self.end != PipeEnd::Write {
+            return Err(());
+        }
+        let mut bytes = 0;
+        loop {
+            {
+                let mut buffer = self.buffer.lock();
+                if buffer.readers == 0 {
+                    return if bytes > 0 { Ok(bytes) } else { Err(()) };
+                }
+                let n = (buf.len() - bytes).min(PIPE_SIZE - buffer.data.len());
+                buffer.data.extend(&buf[bytes..(bytes + n)]);
+                bytes += n;
+                if bytes == buf.len() {
+                    return Ok(bytes);
+                }
+            }
+            if sys::process::has_pending_signal() {
+                return if bytes > 0 { Ok(bytes) } else { Err(()) };
+            }
+            sys::time::halt_preemptible(); // Let the reader run
+        }
+    }
+}
+
+#[test_case]
+fn test_pipe() {
+    let (mut reader, mut writer) = Pipe::new();
+    let mut buf = [0; 5];
+    assert_eq!(writer.write(b"Hello"), Ok(5));
+    assert_eq!(reader.read(&mut buf[0..2]), Ok(2));
+    assert_eq!(reader.read(&mut buf[2..5]), Ok(3));
+    assert_eq!(&buf, b"Hello");
+
+    // The end of file is reached when every write end is closed
+    let mut other_writer = writer.clone();
+    drop(writer);
+    assert_eq!(other_writer.write(b"!"), Ok(1));
+    drop(other_writer);
+    assert_eq!(reader.read(&mut buf), Ok(1));
+    assert_eq!(reader.read(&mut buf), Ok(0));
+
+    // Writing fails when every read end is closed
+    let (reader, mut writer) = Pipe::new();
+    drop(reader);
+    assert_eq!(writer.write(b"Hello"), Err(()));
+}
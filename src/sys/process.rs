@@ -1,115 +1,715 @@
 use crate::sys::fs::{Resource, Device};
 use crate::sys::console::Console;
+use alloc::boxed::Box;
 use alloc::collections::btree_map::BTreeMap;
+use alloc::collections::vec_deque::VecDeque;
+use alloc::format;
 use alloc::string::{String, ToString};
 use alloc::vec;
 use alloc::vec::Vec;
-use core::sync::atomic::{AtomicUsize, Ordering};
+use array_macro::array;
+use core::fmt;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use lazy_static::lazy_static;
-use object::{Object, ObjectSection};
-use spin::Mutex;
+use spin::{Mutex, RwLock};
 
 const MAX_FILE_HANDLES: usize = 1024;
+pub const MAX_PROCS: usize = 16;
 
 lazy_static! {
-    pub static ref PIDS: AtomicUsize = AtomicUsize::new(0);
-    pub static ref PROCESS: Mutex<ProcessData> = Mutex::new(ProcessData::new("/", None)); // TODO
+    pub static ref PROCESS_TABLE: RwLock<[Option<Box<Process>>; MAX_PROCS]> = {
+        let mut table = array![None; MAX_PROCS];
+        table[0] = Some(Box::new(Process::kernel()));
+        RwLock::new(table)
+    };
+    static ref RUN_QUEUE: Mutex<VecDeque<usize>> = Mutex::new(VecDeque::new());
 }
 
+#[derive(Clone)]
 pub struct ProcessData {
-    id: usize,
     env: BTreeMap<String, String>,
     dir: String,
     user: Option<String>,
     file_handles: Vec<Option<Resource>>,
-    code_addr: u64,
+    parent_id: Option<usize>, // None for the kernel and for orphans
+    exit_code: Option<usize>,
+    signal_handlers: [usize; MAX_SIGNALS], // Addresses of the handlers, or 0 for the default action
+    signal_restorer: usize, // Address where the handlers return to
 }
 
 impl ProcessData {
     pub fn new(dir: &str, user: Option<&str>) -> Self {
-        let id = PIDS.fetch_add(1, Ordering::SeqCst);
         let env = BTreeMap::new();
         let dir = dir.to_string();
         let user = user.map(String::from);
-        let code_addr = 0;
         let mut file_handles = vec![None; MAX_FILE_HANDLES];
         file_handles[0] = Some(Resource::Device(Device::Console(Console::new())));
         file_handles[1] = Some(Resource::Device(Device::Console(Console::new())));
         file_handles[2] = Some(Resource::Device(Device::Console(Console::new())));
-        Self { id, env, dir, user, file_handles, code_addr }
+        let parent_id = None;
+        let exit_code = None;
+        let signal_handlers = [0; MAX_SIGNALS];
+        let signal_restorer = 0;
+        Self { env, dir, user, file_handles, parent_id, exit_code, signal_handlers, signal_restorer }
     }
 }
 
+fn current<T>(f: impl FnOnce(&Process) -> T) -> T {
+    let table = PROCESS_TABLE.read();
+    f(table[id()].as_ref().expect("current process not found"))
+}
+
+fn current_mut<T>(f: impl FnOnce(&mut Process) -> T) -> T {
+    let mut table = PROCESS_TABLE.write();
+    f(table[id()].as_mut().expect("current process not found"))
+}
+
+// PID of the process running on the current CPU
 pub fn id() -> usize {
-    PROCESS.lock().id
+    current_cpu().pid.load(Ordering::SeqCst)
 }
 
 pub fn env(key: &str) -> Option<String> {
-    PROCESS.lock().env.get(key).cloned()
+    current(|proc| proc.data.env.get(key).cloned())
 }
 
 pub fn envs() -> BTreeMap<String, String> {
-    PROCESS.lock().env.clone()
+    current(|proc| proc.data.env.clone())
 }
 
 pub fn dir() -> String {
-    PROCESS.lock().dir.clone()
+    current(|proc| proc.data.dir.clone())
 }
 
 pub fn user() -> Option<String> {
-    PROCESS.lock().user.clone()
+    current(|proc| proc.data.user.clone())
 }
 
 pub fn set_env(key: &str, val: &str) {
-    PROCESS.lock().env.insert(key.into(), val.into());
+    current_mut(|proc| proc.data.env.insert(key.into(), val.into()));
 }
 
 pub fn set_dir(dir: &str) {
-    PROCESS.lock().dir = dir.into();
+    current_mut(|proc| proc.data.dir = dir.into());
 }
 
 pub fn set_user(user: &str) {
-    PROCESS.lock().user = Some(user.into())
+    current_mut(|proc| proc.data.user = Some(user.into()));
 }
 
 pub fn create_file_handle(file: Resource) -> Result<usize, ()> {
     let min = 4; // The first 4 file handles are reserved
     let max = MAX_FILE_HANDLES;
-    let proc = &mut *PROCESS.lock();
-    for handle in min..max {
-        if proc.file_handles[handle].is_none() {
-            proc.file_handles[handle] = Some(file);
-            return Ok(handle);
+    current_mut(|proc| {
+        for handle in min..max {
+            if proc.data.file_handles[handle].is_none() {
+                proc.data.file_handles[handle] = Some(file);
+                return Ok(handle);
+            }
         }
-    }
-    Err(())
+        Err(())
+    })
 }
 
 pub fn update_file_handle(handle: usize, file: Resource) {
-    let proc = &mut *PROCESS.lock();
-    proc.file_handles[handle] = Some(file);
+    current_mut(|proc| proc.data.file_handles[handle] = Some(file));
 }
 
 pub fn delete_file_handle(handle: usize) {
-    let proc = &mut *PROCESS.lock();
-    proc.file_handles[handle] = None;
+    current_mut(|proc| {
+        if let Some(file) = proc.data.file_handles.get_mut(handle) {
+            *file = None;
+        }
+    });
+}
+
+// Copy a file handle to the lowest free handle
+pub fn dup(handle: usize) -> Result<usize, ()> {
+    current_mut(|proc| {
+        let file = proc.data.file_handles.get(handle).cloned().flatten().ok_or(())?;
+        let new_handle = proc.data.file_handles.iter().position(|h| h.is_none()).ok_or(())?;
+        proc.data.file_handles[new_handle] = Some(file);
+        Ok(new_handle)
+    })
+}
+
+// Copy a file handle to the given handle, closing it first if needed
+pub fn dup2(handle: usize, new_handle: usize) -> Result<usize, ()> {
+    current_mut(|proc| {
+        let file = proc.data.file_handles.get(handle).cloned().flatten().ok_or(())?;
+        let entry = proc.data.file_handles.get_mut(new_handle).ok_or(())?;
+        *entry = Some(file);
+        Ok(new_handle)
+    })
 }
 
 pub fn file_handle(handle: usize) -> Option<Resource> {
-    let proc = &mut *PROCESS.lock();
-    proc.file_handles[handle].clone()
+    current(|proc| proc.data.file_handles.get(handle).cloned().flatten())
 }
 
 pub fn code_addr() -> u64 {
-    PROCESS.lock().code_addr
+    current(|proc| proc.code_addr)
 }
 
-pub fn set_code_addr(addr: u64) {
-    PROCESS.lock().code_addr = addr;
+// Binaries linked at address 0 are relocated at the code address of their
+// process, while addresses already in the user space are left untouched.
+pub fn ptr_from_addr(addr: u64) -> *mut u8 {
+    let code_addr = code_addr();
+    if addr < code_addr {
+        (code_addr + addr) as *mut u8
+    } else {
+        addr as *mut u8
+    }
 }
 
-pub fn ptr_from_addr(addr: u64) -> *mut u8 {
-    (PROCESS.lock().code_addr + addr) as *mut u8
+// Check that a memory range given by the current process is mapped in its
+// user space, with write access if needed. The kernel process is trusted with
+// any address.
+pub fn is_user_range(addr: u64, len: usize, writable: bool) -> bool {
+    if id() == 0 {
+        return true;
+    }
+    let end = match addr.checked_add(len as u64) {
+        Some(end) if addr >= USER_ADDR && end <= USER_ADDR + USER_SIZE => end,
+        _ => return false,
+    };
+    if len == 0 {
+        return true;
+    }
+    let mapper = unsafe { sys::mem::mapper(VirtAddr::new(sys::mem::PHYS_MEM_OFFSET)) };
+    let start_page = Page::<Size4KiB>::containing_address(VirtAddr::new(addr));
+    let end_page = Page::<Size4KiB>::containing_address(VirtAddr::new(end - 1));
+    Page::range_inclusive(start_page, end_page).all(|page| {
+        match mapper.translate(page.start_address()) {
+            TranslateResult::Mapped { flags, .. } => {
+                flags.contains(PageTableFlags::USER_ACCESSIBLE) && (!writable || flags.contains(PageTableFlags::WRITABLE))
+            }
+            _ => false,
+        }
+    })
+}
+
+// Extend the heap of the current process with zeroed pages and return the
+// address of the new memory, that follows the previous allocation.
+pub fn alloc(size: usize) -> Result<u64, ()> {
+    if id() == 0 {
+        return Err(()); // The kernel has its own heap
+    }
+    let size = (size as u64).checked_add(PAGE_SIZE - 1).ok_or(())? & !(PAGE_SIZE - 1);
+    current_mut(|proc| {
+        let addr = HEAP_ADDR + proc.heap_size;
+        if proc.heap_used + size > MAX_HEAP_SIZE || addr + size > STACK_ADDR - STACK_SIZE {
+            return Err(());
+        }
+        let mut mapper = unsafe { proc.mapper() };
+        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE | PageTableFlags::NO_EXECUTE;
+        // NOTE: The page table is active but the pages were not mapped
+        // before so there is nothing to flush from the TLB.
+        if load(&mut mapper, addr, size, &[], flags).is_err() {
+            proc.free_heap(addr, addr + size); // Release the pages already mapped
+            return Err(());
+        }
+        proc.heap_size += size;
+        proc.heap_used += size;
+        Ok(addr)
+    })
+}
+
+// Release the heap pages of the current process in the given range, lowering
+// the end of the heap when the range reaches it.
+pub fn free(addr: u64, size: usize) -> Result<(), ()> {
+    if id() == 0 {
+        return Err(());
+    }
+    current_mut(|proc| {
+        let heap_end = HEAP_ADDR + proc.heap_size;
+        let end = match addr.checked_add(size as u64) {
+            Some(end) if addr >= HEAP_ADDR && addr % PAGE_SIZE == 0 && end <= heap_end => end,
+            _ => return Err(()),
+        };
+        let end = (end + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
+        proc.heap_used -= proc.free_heap(addr, end);
+        if end == heap_end {
+            proc.heap_size = addr - HEAP_ADDR;
+        }
+        Ok(())
+    })
+}
+
+// Terminate the current process and release its file handles, the rest of its
+// resources will be released when its exit code is collected.
+pub fn exit(code: usize) {
+    if id() == 0 {
+        return; // The kernel cannot exit
+    }
+    current_mut(|proc| {
+        proc.state = ProcessState::Exited;
+        proc.data.exit_code = Some(code);
+        for handle in proc.data.file_handles.iter_mut() {
+            *handle = None;
+        }
+        proc.free_heap(HEAP_ADDR, HEAP_ADDR + proc.heap_size);
+        proc.heap_size = 0;
+        proc.heap_used = 0;
+    });
+    orphan_children(id());
+}
+
+// Stop another process with a SIGKILL, that will make it exit by itself the
+// next time it returns to user mode or checks for signals while blocked in
+// the kernel, so that the resources held by its kernel stack are released.
+pub fn kill(id: usize) -> Result<(), ()> {
+    if id == 0 || id >= MAX_PROCS {
+        return Err(()); // The kernel cannot be killed
+    }
+    if id == self::id() {
+        exit(KILL_CODE);
+        return Ok(());
+    }
+    // NOTE: The table is locked to keep the PID from being reused before
+    // the signal is sent.
+    let table = PROCESS_TABLE.read();
+    match table[id].as_ref() {
+        Some(proc) if proc.state != ProcessState::Exited => {
+            send_signal(id, SIGKILL);
+            Ok(())
+        }
+        _ => Err(()),
+    }
+}
+
+pub fn has_exited() -> bool {
+    current(|proc| proc.state == ProcessState::Exited)
+}
+
+// Fill the given buffer with information about the processes in the table
+// and return the number of entries filled.
+pub fn list(buf: &mut [ProcessInfo]) -> usize {
+    let table = PROCESS_TABLE.read();
+    let procs = table.iter().filter_map(|proc| proc.as_ref());
+    let mut n = 0;
+    for (info, proc) in buf.iter_mut().zip(procs) {
+        *info = ProcessInfo::from(&**proc);
+        n += 1;
+    }
+    n
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ProcessInfo {
+    id: usize,
+    parent_id: usize,
+    state: ProcessState,
+    memory: usize,
+    heap: usize,
+    name: [u8; 32],
+    user: [u8; 32],
+}
+
+impl ProcessInfo {
+    pub fn new() -> Self {
+        Self { id: 0, parent_id: 0, state: ProcessState::Ready, memory: 0, heap: 0, name: [0; 32], user: [0; 32] }
+    }
+
+    fn from(proc: &Process) -> Self {
+        let mut info = Self::new();
+        info.id = proc.id;
+        info.parent_id = proc.data.parent_id.unwrap_or(0);
+        info.state = proc.state;
+        info.memory = user_memory(proc.page_table_frame);
+        info.heap = proc.heap_used as usize;
+        copy_str(&mut info.name, &proc.name);
+        copy_str(&mut info.user, proc.data.user.as_deref().unwrap_or(""));
+        info
+    }
+
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    pub fn parent_id(&self) -> usize {
+        self.parent_id
+    }
+
+    pub fn state(&self) -> ProcessState {
+        self.state
+    }
+
+    // Size of the memory mapped in the user space of the process
+    pub fn memory(&self) -> usize {
+        self.memory
+    }
+
+    // Size of the memory allocated by the process with the ALLOC syscall
+    pub fn heap(&self) -> usize {
+        self.heap
+    }
+
+    pub fn name(&self) -> &str {
+        str_from_buf(&self.name)
+    }
+
+    pub fn user(&self) -> &str {
+        str_from_buf(&self.user)
+    }
+}
+
+// Copy a string into a null padded buffer, truncating it if necessary
+fn copy_str(buf: &mut [u8], s: &str) {
+    let mut n = s.len().min(buf.len());
+    while !s.is_char_boundary(n) {
+        n -= 1;
+    }
+    buf[..n].copy_from_slice(&s.as_bytes()[..n]);
+}
+
+fn str_from_buf(buf: &[u8]) -> &str {
+    let n = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+    core::str::from_utf8(&buf[..n]).unwrap_or("")
+}
+
+// Block until the given child process has exited then remove it from the
+// process table and return its exit code.
+pub fn wait(id: usize) -> Result<usize, ()> {
+    let parent_id = self::id();
+
+    // A child waited by the foreground process receives the keyboard signals
+    let ordering = Ordering::SeqCst;
+    let is_foreground = FOREGROUND.compare_exchange(parent_id, id, ordering, ordering).is_ok();
+
+    let res = (|| loop {
+        {
+            let mut table = PROCESS_TABLE.write();
+            let proc = table.get(id).and_then(|proc| proc.as_ref()).ok_or(())?;
+            if proc.data.parent_id != Some(parent_id) {
+                return Err(());
+            }
+            if let Some(code) = proc.data.exit_code {
+                if !is_in_use(id) {
+                    table[id] = None;
+                    return Ok(code);
+                }
+            }
+        }
+        if has_pending_signal() {
+            return Err(()); // Let the signal be delivered
+        }
+        sys::time::halt_preemptible();
+    })();
+
+    if is_foreground {
+        FOREGROUND.store(parent_id, ordering);
+    }
+    res
+}
+
+// Detach the children of a process that is exiting, nobody will wait for
+// them anymore so they can be reaped as soon as they exit.
+fn orphan_children(parent_id: usize) {
+    let mut table = PROCESS_TABLE.write();
+    for proc in table.iter_mut().flatten() {
+        if proc.data.parent_id == Some(parent_id) {
+            proc.data.parent_id = None;
+        }
+    }
+    reap_zombies(&mut table);
+}
+
+// Remove from the process table the orphans that have exited, except for
+// those whose kernel stack is still used by a CPU.
+fn reap_zombies(table: &mut [Option<Box<Process>>; MAX_PROCS]) {
+    for slot in table.iter_mut().skip(1) {
+        let is_zombie = slot.as_ref().map_or(false, |proc| {
+            proc.data.parent_id.is_none() && proc.data.exit_code.is_some() && !is_in_use(proc.id)
+        });
+        if is_zombie {
+            *slot = None;
+        }
+    }
+}
+
+/*************
+ * Scheduler *
+ *************/
+
+use crate::sys;
+use crate::sys::cpu::MAX_CPUS;
+use crate::sys::fpu::FpuState;
+use crate::sys::gdt::GDT;
+use crate::sys::idt::Registers;
+use x86_64::structures::idt::{InterruptStackFrame, InterruptStackFrameValue};
+
+// Number of timer ticks a process can run before being preempted
+const QUANTUM: usize = 10;
+
+// PID given to the idle loop of an application processor, which runs outside
+// of the process table while there is no process ready for this CPU.
+const IDLE_PID: usize = MAX_PROCS;
+
+const NO_PID: usize = usize::MAX;
+
+// State of the scheduler on each CPU
+struct Cpu {
+    pid: AtomicUsize, // Process running on the CPU
+    prev_pid: AtomicUsize, // Process switched from, whose kernel stack may still be in use
+    ticks_left: AtomicUsize,
+    // Set while the running process is halting the CPU in kernel mode without
+    // holding any lock, which is the only point where kernel code can be
+    // preempted.
+    idle: AtomicBool,
+    idle_context: Mutex<Option<(InterruptStackFrameValue, Registers)>>,
+}
+
+impl Cpu {
+    const fn new() -> Self {
+        Self {
+            pid: AtomicUsize::new(0),
+            prev_pid: AtomicUsize::new(NO_PID),
+            ticks_left: AtomicUsize::new(QUANTUM),
+            idle: AtomicBool::new(false),
+            idle_context: Mutex::new(None),
+        }
+    }
+}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const CPU: Cpu = Cpu::new();
+static CPUS: [Cpu; MAX_CPUS] = [CPU; MAX_CPUS];
+
+fn current_cpu() -> &'static Cpu {
+    &CPUS[sys::cpu::id()]
+}
+
+pub fn set_idle(idle: bool) {
+    current_cpu().idle.store(idle, Ordering::SeqCst);
+}
+
+// Called by each application processor once it is ready to run processes,
+// which is when it is added to the count of CPUs.
+pub fn idle_loop(cpu: usize) -> ! {
+    CPUS[cpu].pid.store(IDLE_PID, Ordering::SeqCst);
+    sys::cpu::add_cpu();
+    loop {
+        sys::time::halt_preemptible();
+    }
+}
+
+fn is_running(id: usize) -> bool {
+    CPUS.iter().take(sys::cpu::count()).any(|cpu| cpu.pid.load(Ordering::SeqCst) == id)
+}
+
+// Check if a process is running or if another CPU has just switched away
+// from it and has not yet left its kernel stack.
+fn is_in_use(id: usize) -> bool {
+    let current = sys::cpu::id();
+    is_running(id) || CPUS.iter().take(sys::cpu::count()).enumerate().any(|(i, cpu)| {
+        i != current && cpu.prev_pid.load(Ordering::SeqCst) == id
+    })
+}
+
+// Called by the timer interrupt handler of each CPU with the context of the
+// interrupted process, which will be replaced by the context of the next
+// process in the run queue when the current one has used its quantum or is
+// idle.
+pub fn schedule(stack_frame: &mut InterruptStackFrame, regs: &mut Registers) {
+    let cpu = current_cpu();
+    // The CPU has left the kernel stack of the previous process to be
+    // interrupted in the current one.
+    cpu.prev_pid.store(NO_PID, Ordering::SeqCst);
+
+    let is_idle = cpu.idle.load(Ordering::SeqCst);
+    let is_user = stack_frame.code_segment & 3 == 3;
+    if !is_idle && !is_user {
+        return;
+    }
+    let ticks_left = cpu.ticks_left.load(Ordering::SeqCst).saturating_sub(1);
+    cpu.ticks_left.store(ticks_left, Ordering::SeqCst);
+    if ticks_left > 0 && !is_idle {
+        return;
+    }
+    switch(stack_frame, regs);
+}
+
+// Save the context of the current process and replace it with the context of
+// the next process in the run queue that can run on this CPU. The kernel
+// process stays on the bootstrap processor, while the application processors
+// go back to their idle loop when their process exits.
+//
+// NOTE: We only switch when the interrupted process was in user mode,
+// exiting, or halting with `sys::time::halt_preemptible`, so it doesn't hold
+// any lock.
+pub fn switch(stack_frame: &mut InterruptStackFrame, regs: &mut Registers) {
+    let cpu_id = sys::cpu::id();
+    let cpu = &CPUS[cpu_id];
+    let prev_id = cpu.pid.load(Ordering::SeqCst);
+    let mut queue = RUN_QUEUE.lock();
+    let is_running = |table: &[Option<Box<Process>>]| {
+        table.get(prev_id).and_then(|proc| proc.as_ref()).map_or(false, |proc| {
+            proc.state == ProcessState::Running
+        })
+    };
+
+    // Keep the current process when there is nothing else to run, unless it
+    // has exited on an AP that must go back to its idle loop. The process
+    // table is not touched on the BSP, where the heap might not be ready yet.
+    let is_bsp_or_idle = cpu_id == 0 || prev_id == IDLE_PID;
+    if queue.is_empty() && (is_bsp_or_idle || is_running(&PROCESS_TABLE.read()[..])) {
+        return;
+    }
+
+    let mut table = PROCESS_TABLE.write();
+    let is_runnable = |id: usize| (id != 0 || cpu_id == 0) && !is_in_use(id);
+    let is_prev_running = is_running(&table[..]);
+    let next_id = match queue.iter().position(|&id| is_runnable(id)) {
+        Some(i) => queue.remove(i).expect("scheduled process not found"),
+        None if is_prev_running || prev_id == IDLE_PID || cpu_id == 0 => return,
+        None => IDLE_PID,
+    };
+
+    if let Some(prev) = table.get_mut(prev_id).and_then(|proc| proc.as_mut()) {
+        if prev.state == ProcessState::Running {
+            prev.stack_frame = **stack_frame;
+            prev.registers = *regs;
+            prev.fpu_state.save();
+            prev.state = ProcessState::Ready;
+            queue.push_back(prev_id);
+        }
+        cpu.prev_pid.store(prev_id, Ordering::SeqCst);
+    } else if prev_id == IDLE_PID {
+        *cpu.idle_context.lock() = Some((**stack_frame, *regs));
+    }
+
+    let page_table_frame = if next_id == IDLE_PID {
+        let (frame, registers) = cpu.idle_context.lock().take().expect("idle context not found");
+        unsafe {
+            stack_frame.as_mut().write(frame);
+        }
+        *regs = registers;
+        table[0].as_ref().expect("kernel process not found").page_table_frame
+    } else {
+        let next = table[next_id].as_mut().expect("scheduled process not found");
+        next.state = ProcessState::Running;
+        unsafe {
+            stack_frame.as_mut().write(next.stack_frame);
+        }
+        *regs = next.registers;
+        next.fpu_state.restore();
+        if let Some(addr) = next.kernel_stack_top() {
+            sys::gdt::set_kernel_stack(addr);
+        }
+        next.page_table_frame
+    };
+    let (frame, flags) = Cr3::read();
+    if frame != page_table_frame {
+        unsafe { Cr3::write(page_table_frame, flags) };
+    }
+
+    cpu.pid.store(next_id, Ordering::SeqCst);
+    cpu.ticks_left.store(QUANTUM, Ordering::SeqCst);
+    cpu.idle.store(false, Ordering::SeqCst);
+}
+
+/***********
+ * Signals *
+ ***********/
+
+pub const SIGINT: usize = 2;
+pub const SIGKILL: usize = 9;
+const MAX_SIGNALS: usize = 32;
+
+// The signals waiting to be delivered to each process are kept outside of the
+// process table to be set from interrupt handlers.
+#[allow(clippy::declare_interior_mutable_const)]
+const NO_SIGNAL: AtomicUsize = AtomicUsize::new(0);
+static PENDING_SIGNALS: [AtomicUsize; MAX_PROCS] = [NO_SIGNAL; MAX_PROCS];
+
+// PID of the process receiving the signals sent from the keyboard
+static FOREGROUND: AtomicUsize = AtomicUsize::new(0);
+
+pub fn send_signal(id: usize, signal: usize) {
+    if id > 0 && id < MAX_PROCS && signal < MAX_SIGNALS {
+        PENDING_SIGNALS[id].fetch_or(1 << signal, Ordering::SeqCst);
+    }
+}
+
+// Called by the keyboard handler when Ctrl-C is pressed, returns false when
+// the kernel is in the foreground.
+pub fn interrupt_foreground() -> bool {
+    let id = FOREGROUND.load(Ordering::SeqCst);
+    send_signal(id, SIGINT);
+    id > 0
+}
+
+// Let blocking syscalls return early to deliver a signal
+pub fn has_pending_signal() -> bool {
+    PENDING_SIGNALS[id()].load(Ordering::SeqCst) != 0
+}
+
+pub fn set_signal_handler(signal: usize, handler: usize, restorer: usize) -> Result<(), ()> {
+    if id() == 0 || signal == 0 || signal >= MAX_SIGNALS || signal == SIGKILL {
+        return Err(());
+    }
+    current_mut(|proc| {
+        proc.data.signal_handlers[signal] = handler;
+        proc.data.signal_restorer = restorer;
+    });
+    Ok(())
+}
+
+// Deliver a pending signal to the current process before it returns to user
+// mode, by terminating it or by calling its handler on its own stack. The
+// handler will return to the restorer that resumes the interrupted context
+// with the SIGRETURN syscall.
+pub fn handle_signals(stack_frame: &mut InterruptStackFrame, regs: &mut Registers) {
+    if stack_frame.code_segment & 3 != 3 {
+        return;
+    }
+    let id = id();
+    let pending = PENDING_SIGNALS[id].swap(0, Ordering::SeqCst);
+    if pending == 0 {
+        return;
+    }
+    let signal = pending.trailing_zeros() as usize;
+    PENDING_SIGNALS[id].fetch_or(pending & !(1 << signal), Ordering::SeqCst);
+
+    // NOTE: A signal received while its handler is running terminates the
+    // process, so pressing Ctrl-C twice always works.
+    let (handler, restorer) = current(|proc| {
+        if proc.signal_context.is_some() {
+            (0, 0)
+        } else {
+            (proc.data.signal_handlers[signal], proc.data.signal_restorer)
+        }
+    });
+    let sp = stack_frame.stack_pointer.as_u64().saturating_sub(128) & !15; // Skip the red zone
+    let sp = sp.saturating_sub(8); // Return address pushed like a call
+    if handler == 0 || !is_user_range(sp, 8, true) {
+        exit(128 + signal);
+        switch(stack_frame, regs);
+        return;
+    }
+    unsafe { *(sp as *mut u64) = restorer as u64 };
+    let mut fpu_state = FpuState::new();
+    fpu_state.save();
+    current_mut(|proc| proc.signal_context = Some((**stack_frame, *regs, fpu_state)));
+    let mut frame = **stack_frame;
+    frame.instruction_pointer = VirtAddr::new(handler as u64);
+    frame.stack_pointer = VirtAddr::new(sp);
+    unsafe { stack_frame.as_mut().write(frame) };
+    regs.rdi = signal;
+}
+
+pub fn has_signal_context() -> bool {
+    current(|proc| proc.signal_context.is_some())
+}
+
+// Resume the context interrupted by a signal after its handler returned
+pub fn restore_signal_context(stack_frame: &mut InterruptStackFrame, regs: &mut Registers) {
+    if let Some((frame, registers, fpu_state)) = current_mut(|proc| proc.signal_context.take()) {
+        unsafe { stack_frame.as_mut().write(frame) };
+        *regs = registers;
+        fpu_state.restore();
+    }
 }
 
 /************************
@@ -119,102 +719,601 @@ pub fn ptr_from_addr(addr: u64) -> *mut u8 {
 // See https://nfil.dev/kernel/rust/coding/rust-kernel-to-userspace-and-back/
 // And https://github.com/WartaPoirier-corp/ananos/blob/dev/docs/notes/context-switch.md
 
-use crate::sys;
-use crate::sys::gdt::GDT;
-use core::sync::atomic::AtomicU64;
-use x86_64::VirtAddr;
-use x86_64::structures::paging::{Mapper, FrameAllocator};
+use x86_64::{PhysAddr, VirtAddr};
+use x86_64::registers::control::Cr3;
+use x86_64::structures::paging::{FrameAllocator, FrameDeallocator, Mapper, OffsetPageTable, PhysFrame, Size4KiB};
 use x86_64::structures::paging::{Page, PageTableFlags};
+use x86_64::structures::paging::mapper::{Translate, TranslateResult};
 
-static CODE_ADDR: AtomicU64 = AtomicU64::new(0x40_0000);
-static STACK_ADDR: AtomicU64 = AtomicU64::new(0x80_0000);
+// The user space of every process is located in the last level 4 entry of
+// the lower half of the address space, which is private, while the other
+// entries point to the tables of the kernel so that its mappings added after
+// the creation of a process are also found in its address space.
+const USER_ADDR: u64 = 0x7F80_0000_0000;
+const USER_SIZE: u64 = 0x4000_0000;
+const USER_L4_INDEX: usize = 255;
+const CODE_ADDR: u64 = USER_ADDR;
+const HEAP_ADDR: u64 = USER_ADDR + USER_SIZE / 2; // Bottom of the heap
+const STACK_ADDR: u64 = USER_ADDR + USER_SIZE; // Top of the stack
+const STACK_SIZE: u64 = 64 * 1024; // Mapped below the top of the stack
+const MAX_ARGS_SIZE: u64 = STACK_SIZE / 4; // Part of the stack given to the arguments
+const MAX_HEAP_SIZE: u64 = 8 << 20; // MB
 const PAGE_SIZE: u64 = 4 * 1024;
+pub const KERNEL_STACK_SIZE: usize = 16 * 1024;
+const KILL_CODE: usize = 128 + 9; // Reported like a SIGKILL by POSIX shells
+pub const FAULT_CODE: usize = 128 + 11; // Reported like a SIGSEGV
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProcessState {
+    Ready,
+    Running,
+    Exited,
+}
 
 pub struct Process {
-    stack_addr: u64,
+    id: usize,
+    name: String,
+    state: ProcessState,
     code_addr: u64,
-    entry_point: u64,
+    page_table_frame: PhysFrame,
+    stack_frame: InterruptStackFrameValue,
+    registers: Registers,
+    fpu_state: FpuState,
+    kernel_stack: Vec<u8>,
+    heap_size: u64, // Distance between the bottom and the end of the heap
+    heap_used: u64, // Size of the pages mapped in the heap
+    signal_context: Option<(InterruptStackFrameValue, Registers, FpuState)>, // Saved while a signal handler runs
+    data: ProcessData,
 }
 
 impl Process {
-    pub fn create(bin: &[u8]) -> Result<Process, ()> {
-        let mut mapper = unsafe { sys::mem::mapper(VirtAddr::new(sys::mem::PHYS_MEM_OFFSET)) };
-        let mut frame_allocator = unsafe { sys::mem::BootInfoFrameAllocator::init(sys::mem::MEMORY_MAP.unwrap()) };
+    // The kernel runs as the first process, on the stack given by the
+    // bootloader, and its context is only saved when it is preempted.
+    fn kernel() -> Self {
+        Self {
+            id: 0,
+            name: "kernel".to_string(),
+            state: ProcessState::Running,
+            code_addr: 0,
+            page_table_frame: Cr3::read().0,
+            stack_frame: empty_stack_frame(),
+            registers: Registers::default(),
+            fpu_state: FpuState::new(),
+            kernel_stack: Vec::new(),
+            heap_size: 0,
+            heap_used: 0,
+            signal_context: None,
+            data: ProcessData::new("/", None),
+        }
+    }
 
-        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE;
+    // Load a binary in a new address space and add it to the run queue
+    pub fn spawn(bin: &[u8], args: &[&str]) -> Result<usize, ()> {
+        let data = current(|proc| {
+            let mut data = ProcessData::new(&proc.data.dir, proc.data.user.as_deref());
+            data.env = proc.data.env.clone();
+            // NOTE: The handles are copied so the child has its own offsets
+            data.file_handles = proc.data.file_handles.clone();
+            data.parent_id = Some(proc.id);
+            data
+        });
+        let (stack_pointer, stack) = stack_layout(args, &data.env)?;
 
-        let stack_addr = STACK_ADDR.fetch_add(PAGE_SIZE, Ordering::SeqCst);
-        let frame = frame_allocator.allocate_frame().unwrap();
-        let page = Page::containing_address(VirtAddr::new(stack_addr));
-        unsafe {
-            mapper.map_to(page, frame, flags, &mut frame_allocator).unwrap().flush();
+        let page_table_frame = create_page_table()?;
+        let mut mapper = unsafe {
+            let page_table = sys::mem::page_table(page_table_frame);
+            OffsetPageTable::new(page_table, VirtAddr::new(sys::mem::PHYS_MEM_OFFSET))
+        };
+
+        let code_addr = CODE_ADDR;
+        let res = if bin.starts_with(b"\x7FELF") { // ELF binary
+            parse_elf(bin).map_err(|err| {
+                log!("Could not load ELF binary: {}\n", err);
+            }).and_then(|(entry_point, segments)| {
+                segments.iter().try_for_each(|segment| {
+                    load(&mut mapper, segment.addr, segment.size, segment.data, segment.flags)
+                }).map(|_| entry_point)
+            })
+        } else { // Raw binary
+            let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE;
+            load(&mut mapper, code_addr, bin.len() as u64, bin, flags).map(|_| code_addr)
+        };
+        let stack_flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE | PageTableFlags::NO_EXECUTE;
+        let res = res.and_then(|entry_point| {
+            load(&mut mapper, STACK_ADDR - STACK_SIZE, STACK_SIZE, &[], stack_flags)?;
+            load(&mut mapper, stack_pointer, stack.len() as u64, &stack, stack_flags)?;
+            Ok(entry_point)
+        });
+        let entry_point = match res {
+            Ok(entry_point) => entry_point,
+            Err(()) => {
+                free_page_table(page_table_frame);
+                return Err(());
+            }
+        };
+
+        // The process will start in user mode at its entry point the first
+        // time the scheduler switches to it.
+        let stack_frame = InterruptStackFrameValue {
+            instruction_pointer: VirtAddr::new(entry_point),
+            code_segment: GDT.1.user_code.0 as u64,
+            cpu_flags: 0x200, // Interrupts enabled
+            stack_pointer: VirtAddr::new(stack_pointer),
+            stack_segment: GDT.1.user_data.0 as u64,
+        };
+        // The arguments are also given in registers for programs that don't
+        // want to read them from the stack.
+        let registers = Registers {
+            rdi: args.len(),
+            rsi: (stack_pointer + 8) as usize,
+            rdx: (stack_pointer + 8 * (args.len() as u64 + 2)) as usize,
+            ..Registers::default()
+        };
+        let kernel_stack = vec![0; KERNEL_STACK_SIZE];
+
+        let mut table = PROCESS_TABLE.write();
+        reap_zombies(&mut table);
+        let id = match table.iter().position(|proc| proc.is_none()) {
+            Some(id) => id,
+            None => {
+                free_page_table(page_table_frame);
+                return Err(());
+            }
+        };
+        let name = args.first().map_or("", |arg| sys::fs::filename(arg)).to_string();
+        let state = ProcessState::Ready;
+        let heap_size = 0;
+        let heap_used = 0;
+        let signal_context = None;
+        let fpu_state = FpuState::new();
+        let proc = Process { id, name, state, code_addr, page_table_frame, stack_frame, registers, fpu_state, kernel_stack, heap_size, heap_used, signal_context, data };
+        PENDING_SIGNALS[id].store(0, Ordering::SeqCst);
+        table[id] = Some(Box::new(proc));
+        drop(table); // The run queue must be locked first
+        RUN_QUEUE.lock().push_back(id);
+
+        Ok(id)
+    }
+
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    pub fn state(&self) -> ProcessState {
+        self.state
+    }
+
+    unsafe fn mapper(&mut self) -> OffsetPageTable<'static> {
+        let page_table = sys::mem::page_table(self.page_table_frame);
+        OffsetPageTable::new(page_table, VirtAddr::new(sys::mem::PHYS_MEM_OFFSET))
+    }
+
+    // Unmap the heap pages mapped in the given range, release their frames and
+    // return their size
+    fn free_heap(&mut self, start: u64, end: u64) -> u64 {
+        let mut size = 0;
+        if start >= end {
+            return size;
+        }
+        let mut mapper = unsafe { self.mapper() };
+        let start_page = Page::<Size4KiB>::containing_address(VirtAddr::new(start));
+        let end_page = Page::<Size4KiB>::containing_address(VirtAddr::new(end - 1));
+        for page in Page::range_inclusive(start_page, end_page) {
+            if let Ok((frame, flush)) = mapper.unmap(page) {
+                flush.flush();
+                sys::mem::deallocate_frame(frame);
+                size += PAGE_SIZE;
+            }
+        }
+        size
+    }
+
+    fn kernel_stack_top(&self) -> Option<VirtAddr> {
+        if self.kernel_stack.is_empty() {
+            return None;
         }
+        let addr = VirtAddr::from_ptr(self.kernel_stack.as_ptr()) + self.kernel_stack.len();
+        Some(addr.align_down(16u64))
+    }
+}
 
-        let code_addr = CODE_ADDR.fetch_add(PAGE_SIZE, Ordering::SeqCst);
-        let frame = frame_allocator.allocate_frame().unwrap();
-        for i in 0..1024 {
-            let addr = code_addr + i * PAGE_SIZE;
-            let page = Page::containing_address(VirtAddr::new(addr));
-            unsafe {
-                mapper.map_to(page, frame, flags, &mut frame_allocator).unwrap().flush();
+impl Drop for Process {
+    fn drop(&mut self) {
+        // The kernel process is never dropped and doesn't own its page table
+        if self.id != 0 {
+            free_page_table(self.page_table_frame);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ElfError {
+    Malformed,
+    UnsupportedArch,
+    NotExecutable,
+    DynamicallyLinked,
+    InvalidSegment,
+}
+
+impl fmt::Display for ElfError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let msg = match self {
+            ElfError::Malformed => "malformed ELF file",
+            ElfError::UnsupportedArch => "unsupported architecture, expected x86_64",
+            ElfError::NotExecutable => "not an executable file",
+            ElfError::DynamicallyLinked => "dynamic linking is not supported",
+            ElfError::InvalidSegment => "segment outside of the code area",
+        };
+        write!(f, "{}", msg)
+    }
+}
+
+// Loadable segment of an ELF binary at its final address in the user space
+#[derive(Debug, Clone)]
+pub struct Segment<'a> {
+    pub addr: u64,
+    pub size: u64, // Size in memory, the part not in the data is the BSS
+    pub data: &'a [u8],
+    pub flags: PageTableFlags,
+}
+
+impl Segment<'_> {
+    pub fn is_writable(&self) -> bool {
+        self.flags.contains(PageTableFlags::WRITABLE)
+    }
+
+    pub fn is_executable(&self) -> bool {
+        !self.flags.contains(PageTableFlags::NO_EXECUTE)
+    }
+}
+
+// Check that an ELF binary can be loaded without relocations and return its
+// entry point with its PT_LOAD segments. Binaries linked at address 0 are
+// moved to the code address of the user space.
+pub fn parse_elf(bin: &[u8]) -> Result<(u64, Vec<Segment>), ElfError> {
+    use object::elf::{ET_EXEC, PF_W, PF_X, PT_DYNAMIC, PT_INTERP, PT_LOAD};
+    use object::read::elf::{ElfFile64, FileHeader, ProgramHeader};
+    use object::Object;
+
+    let obj = object::File::parse(bin).map_err(|_| ElfError::Malformed)?;
+    if obj.format() != object::BinaryFormat::Elf {
+        return Err(ElfError::Malformed);
+    }
+    if obj.architecture() != object::Architecture::X86_64 {
+        return Err(ElfError::UnsupportedArch);
+    }
+    let elf = ElfFile64::<object::Endianness>::parse(bin).map_err(|_| ElfError::Malformed)?;
+    let endian = elf.endian();
+    if elf.raw_header().e_type(endian) != ET_EXEC {
+        return Err(ElfError::NotExecutable);
+    }
+
+    let relocate = |addr: u64| if addr < CODE_ADDR { CODE_ADDR + addr } else { addr };
+    let mut segments = Vec::new();
+    for header in elf.raw_segments() {
+        match header.p_type(endian) {
+            PT_LOAD => {}
+            PT_DYNAMIC | PT_INTERP => return Err(ElfError::DynamicallyLinked),
+            _ => continue,
+        }
+        let data = header.data(endian, bin).map_err(|_| ElfError::Malformed)?;
+        let size = header.p_memsz(endian);
+        if (data.len() as u64) > size {
+            return Err(ElfError::Malformed);
+        }
+        let addr = relocate(header.p_vaddr(endian));
+        match addr.checked_add(size) {
+            Some(end) if end <= HEAP_ADDR => {}
+            _ => return Err(ElfError::InvalidSegment),
+        }
+        let p_flags = header.p_flags(endian);
+        let mut flags = PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
+        if p_flags & PF_W != 0 {
+            flags |= PageTableFlags::WRITABLE;
+        }
+        if p_flags & PF_X == 0 {
+            flags |= PageTableFlags::NO_EXECUTE;
+        }
+        segments.push(Segment { addr, size, data, flags });
+    }
+
+    let entry_point = relocate(obj.entry());
+    let is_valid_entry = segments.iter().any(|segment| {
+        segment.is_executable() && segment.addr <= entry_point && entry_point < segment.addr + segment.size
+    });
+    if !is_valid_entry {
+        return Err(ElfError::Malformed);
+    }
+
+    Ok((entry_point, segments))
+}
+
+// Lay out the arguments and the environment at the top of the user stack as
+// described by the System V ABI, with argc at the returned stack pointer
+// followed by the NULL terminated argv and envp arrays, and then the strings
+// they point to. They must fit in a part of the stack to leave the rest to the
+// program.
+fn stack_layout(args: &[&str], env: &BTreeMap<String, String>) -> Result<(u64, Vec<u8>), ()> {
+    let vars: Vec<String> = env.iter().map(|(key, val)| format!("{}={}", key, val)).collect();
+    let strings: Vec<&str> = args.iter().copied().chain(vars.iter().map(String::as_str)).collect();
+
+    let strings_size: u64 = strings.iter().map(|s| s.len() as u64 + 1).sum();
+    let pointers_size = 8 * (1 + args.len() + 1 + vars.len() + 1) as u64;
+    if strings_size + pointers_size + 32 > MAX_ARGS_SIZE { // With the alignments
+        return Err(());
+    }
+    let strings_addr = (STACK_ADDR - strings_size) & !0xF;
+    let stack_pointer = (strings_addr - pointers_size) & !0xF;
+
+    let mut stack = vec![0; (STACK_ADDR - stack_pointer) as usize];
+    let mut addrs = Vec::with_capacity(strings.len());
+    let mut addr = strings_addr;
+    for s in &strings {
+        let offset = (addr - stack_pointer) as usize;
+        stack[offset..(offset + s.len())].copy_from_slice(s.as_bytes());
+        addrs.push(addr);
+        addr += s.len() as u64 + 1;
+    }
+    let (argv, envp) = addrs.split_at(args.len());
+    let mut pointers = Vec::with_capacity(pointers_size as usize / 8);
+    pointers.push(args.len() as u64);
+    pointers.extend_from_slice(argv);
+    pointers.push(0);
+    pointers.extend_from_slice(envp);
+    pointers.push(0);
+    for (i, ptr) in pointers.iter().enumerate() {
+        stack[(i * 8)..(i * 8 + 8)].copy_from_slice(&ptr.to_le_bytes());
+    }
+
+    Ok((stack_pointer, stack))
+}
+
+fn empty_stack_frame() -> InterruptStackFrameValue {
+    InterruptStackFrameValue {
+        instruction_pointer: VirtAddr::new(0),
+        code_segment: 0,
+        cpu_flags: 0,
+        stack_pointer: VirtAddr::new(0),
+        stack_segment: 0,
+    }
+}
+
+// Create a level 4 page table sharing the tables of the kernel, with an empty
+// entry for the user space that will get its own tables when it is mapped.
+fn create_page_table() -> Result<PhysFrame, ()> {
+    let kernel_frame = PROCESS_TABLE.read()[0].as_ref().map(|proc| proc.page_table_frame).ok_or(())?;
+    let kernel_l4 = unsafe { sys::mem::page_table(kernel_frame) };
+    if !kernel_l4[USER_L4_INDEX].is_unused() {
+        return Err(()); // The user space is already used by the kernel
+    }
+
+    let l4_frame = sys::mem::allocate_frame().ok_or(())?;
+    let l4 = unsafe { sys::mem::page_table(l4_frame) };
+    l4.zero();
+    for (user_entry, kernel_entry) in l4.iter_mut().zip(kernel_l4.iter()) {
+        *user_entry = kernel_entry.clone();
+    }
+
+    Ok(l4_frame)
+}
+
+// Release the user space of a page table with the page table itself. This
+// must not be called while the page table is active.
+fn free_page_table(l4_frame: PhysFrame) {
+    debug_assert!(Cr3::read().0 != l4_frame);
+    free_user_space(l4_frame);
+    sys::mem::deallocate_frame(l4_frame);
+}
+
+// Release the pages mapped in the user space of a page table and the tables
+// mapping them.
+fn free_user_space(l4_frame: PhysFrame) {
+    debug_assert!(Cr3::read().0 != l4_frame);
+    let l4 = unsafe { sys::mem::page_table(l4_frame) };
+    let entry = &mut l4[USER_L4_INDEX];
+    if let Ok(l3_frame) = entry.frame() {
+        free_table(l3_frame, 3, &mut *sys::mem::frame_allocator());
+    }
+    entry.set_unused();
+}
+
+// Count the memory mapped in the user space of a page table
+fn user_memory(l4_frame: PhysFrame) -> usize {
+    fn count_pages(frame: PhysFrame, level: usize) -> usize {
+        let table = unsafe { sys::mem::page_table(frame) };
+        table.iter().filter_map(|entry| entry.frame().ok()).map(|next_frame| {
+            if level > 1 { count_pages(next_frame, level - 1) } else { 1 }
+        }).sum()
+    }
+
+    let l4 = unsafe { sys::mem::page_table(l4_frame) };
+    if let Ok(l3_frame) = l4[USER_L4_INDEX].frame() {
+        return count_pages(l3_frame, 3) * PAGE_SIZE as usize;
+    }
+    0
+}
+
+// Recursively release the frames mapped by a page table and its sub tables
+fn free_table(frame: PhysFrame, level: usize, frame_allocator: &mut impl FrameDeallocator<Size4KiB>) {
+    let table = unsafe { sys::mem::page_table(frame) };
+    for entry in table.iter_mut() {
+        if let Ok(next_frame) = entry.frame() {
+            if level > 1 {
+                free_table(next_frame, level - 1, frame_allocator);
+            } else {
+                unsafe { frame_allocator.deallocate_frame(next_frame) };
             }
         }
+        entry.set_unused();
+    }
+    unsafe { frame_allocator.deallocate_frame(frame) };
+}
 
-        let mut entry_point = 0;
-        let code_ptr = code_addr as *mut u8;
-        if &bin[1..4] == b"ELF" { // ELF binary
-            if let Ok(obj) = object::File::parse(bin) {
-                entry_point = obj.entry();
-                for section in obj.sections() {
-                    if let Ok(name) = section.name() {
-                        let addr = section.address() as usize;
-                        if name.is_empty() || addr == 0 {
-                            continue;
-                        }
-                        if let Ok(data) = section.data() {
-                            unsafe {
-                                for (i, op) in data.iter().enumerate() {
-                                    let ptr = code_ptr.add(addr + i);
-                                    core::ptr::write(ptr, *op);
-                                }
-                            }
-                        }
-                    }
+// Copy data into the user space of a page table and zero the rest of the
+// memory range, allocating pages when they are not already mapped. A page
+// shared by multiple segments gets the union of their permissions.
+fn load(mapper: &mut OffsetPageTable, addr: u64, size: u64, data: &[u8], flags: PageTableFlags) -> Result<(), ()> {
+    let len = data.len() as u64;
+    if len > size || addr < USER_ADDR || addr + size > USER_ADDR + USER_SIZE {
+        return Err(());
+    }
+    let table_flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE;
+    let start_page = Page::containing_address(VirtAddr::new(addr));
+    let end_page = Page::containing_address(VirtAddr::new(addr + size.max(1) - 1));
+    for page in Page::range_inclusive(start_page, end_page) {
+        let frame = match mapper.translate(page.start_address()) {
+            TranslateResult::Mapped { frame, flags: page_flags, .. } => {
+                let mut new_flags = page_flags | flags;
+                if !page_flags.contains(PageTableFlags::NO_EXECUTE) || !flags.contains(PageTableFlags::NO_EXECUTE) {
+                    new_flags.remove(PageTableFlags::NO_EXECUTE);
                 }
+                if new_flags != page_flags {
+                    // The page table is not active so there is nothing to flush
+                    unsafe { mapper.update_flags(page, new_flags).map_err(|_| ())?.ignore() };
+                }
+                PhysFrame::containing_address(frame.start_address())
             }
-        } else { // Raw binary
-            unsafe {
-                for (i, op) in bin.iter().enumerate() {
-                    let ptr = code_ptr.add(i);
-                    core::ptr::write(ptr, *op);
+            _ => {
+                let mut frame_allocator = sys::mem::frame_allocator();
+                let frame = frame_allocator.allocate_frame().ok_or(())?;
+                let ptr = sys::mem::phys_to_virt(frame.start_address()).as_mut_ptr::<u8>();
+                unsafe {
+                    core::ptr::write_bytes(ptr, 0, PAGE_SIZE as usize);
+                    mapper.map_to_with_table_flags(page, frame, flags, table_flags, &mut *frame_allocator).map_err(|_| ())?.ignore();
                 }
+                frame
+            }
+        };
+
+        // Copy the part of the data that belongs to this page and zero the
+        // part of the BSS that follows it.
+        let page_addr = page.start_address().as_u64();
+        let start = addr.max(page_addr);
+        let end = (addr + size).min(page_addr + PAGE_SIZE);
+        if start < end {
+            let phys_addr = PhysAddr::new(frame.start_address().as_u64() + start - page_addr);
+            let dst = sys::mem::phys_to_virt(phys_addr).as_mut_ptr::<u8>();
+            let copy_end = end.min(addr + len).max(start);
+            let src = &data[((start - addr) as usize)..((copy_end - addr) as usize)];
+            unsafe {
+                core::ptr::copy_nonoverlapping(src.as_ptr(), dst, src.len());
+                core::ptr::write_bytes(dst.add(src.len()), 0, (end - copy_end) as usize);
             }
         }
+    }
+    Ok(())
+}
 
-        set_code_addr(code_addr);
+#[test_case]
+fn test_list() {
+    let mut buf = [ProcessInfo::new(); MAX_PROCS];
+    assert!(list(&mut buf) > 0);
+    assert_eq!(buf[0].id(), 0);
+    assert_eq!(buf[0].name(), "kernel");
 
-        Ok(Process { stack_addr, code_addr, entry_point })
-    }
+    let mut name = [0; 4];
+    copy_str(&mut name, "abcé");
+    assert_eq!(str_from_buf(&name), "abc");
+}
 
-    // Switch to user mode and execute the program
-    pub fn exec(&self) {
-        //x86_64::instructions::tlb::flush_all();
-        let data = GDT.1.user_data.0;
-        let code = GDT.1.user_code.0;
-        unsafe {
-            asm!(
-                "cli",        // Disable interrupts
-                "push rax",   // Stack segment (SS)
-                "push rsi",   // Stack pointer (RSP)
-                "push 0x200", // RFLAGS with interrupts enabled
-                "push rdx",   // Code segment (CS)
-                "push rdi",   // Instruction pointer (RIP)
-                "iretq",
-                in("rax") data,
-                in("rsi") self.stack_addr,
-                in("rdx") code,
-                in("rdi") self.code_addr + self.entry_point,
-            );
-        }
-    }
+#[test_case]
+fn test_dup() {
+    let handle = dup(1).unwrap();
+    assert!(file_handle(handle).is_some());
+    assert_eq!(dup2(handle, handle), Ok(handle));
+    delete_file_handle(handle);
+    assert_eq!(dup(handle), Err(()));
+    assert_eq!(dup2(1, MAX_FILE_HANDLES), Err(()));
+}
+
+#[test_case]
+fn test_signal() {
+    assert_eq!(set_signal_handler(SIGINT, 1, 1), Err(())); // The kernel cannot handle signals
+    send_signal(0, SIGINT);
+    assert!(!has_pending_signal());
+    assert!(!interrupt_foreground());
+}
+
+#[test_case]
+fn test_wait() {
+    assert_eq!(wait(0), Err(())); // Not a child of the kernel
+    assert_eq!(wait(MAX_PROCS), Err(()));
+}
+
+#[test_case]
+fn test_kill() {
+    use crate::sys::fs::FileIO;
+    use crate::sys::pipe::Pipe;
+
+    // Raw binary reading a byte from its standard input before exiting
+    let bin = [
+        0x48, 0xC7, 0xC0, 0x04, 0x00, 0x00, 0x00, // mov rax, 4 ; READ
+        0x48, 0xC7, 0xC7, 0x00, 0x00, 0x00, 0x00, // mov rdi, 0 ; stdin
+        0x48, 0x89, 0xE6,                         // mov rsi, rsp
+        0x48, 0xC7, 0xC2, 0x01, 0x00, 0x00, 0x00, // mov rdx, 1
+        0xCD, 0x80,                               // int 0x80
+        0x48, 0xC7, 0xC0, 0x09, 0x00, 0x00, 0x00, // mov rax, 9 ; EXIT
+        0x48, 0xC7, 0xC7, 0x00, 0x00, 0x00, 0x00, // mov rdi, 0
+        0xCD, 0x80,                               // int 0x80
+    ];
+    let (reader, mut writer) = Pipe::new();
+    let stdin = file_handle(0).unwrap();
+    update_file_handle(0, Resource::Pipe(reader)); // Inherited by the child
+    let id = Process::spawn(&bin, &["read"]).unwrap();
+    update_file_handle(0, stdin);
+
+    let memory = PROCESS_TABLE.read()[id].as_ref().map(|proc| user_memory(proc.page_table_frame));
+    assert!(memory >= Some((STACK_SIZE + PAGE_SIZE) as usize)); // Stack and code
+
+    sys::time::sleep(0.1); // Let the child block on the empty pipe
+    assert_eq!(kill(id), Ok(()));
+    assert_eq!(wait(id), Ok(KILL_CODE));
+    assert_eq!(writer.write(b"x"), Err(())); // The read end has been closed
+    assert_eq!(kill(id), Err(()));
+}
+
+#[test_case]
+fn test_stack_layout() {
+    use core::convert::TryInto;
+
+    let mut env = BTreeMap::new();
+    env.insert("HOME".to_string(), "/usr/admin".to_string());
+    let (stack_pointer, stack) = stack_layout(&["hello", "world"], &env).unwrap();
+    assert_eq!(stack_pointer % 16, 0);
+    assert_eq!(stack_pointer + stack.len() as u64, STACK_ADDR);
+
+    let read_u64 = |i: usize| u64::from_le_bytes(stack[(i * 8)..(i * 8 + 8)].try_into().unwrap());
+    let read_str = |addr: u64| {
+        let offset = (addr - stack_pointer) as usize;
+        let len = stack[offset..].iter().position(|&b| b == 0).unwrap();
+        core::str::from_utf8(&stack[offset..(offset + len)]).unwrap()
+    };
+    assert_eq!(read_u64(0), 2);
+    assert_eq!(read_str(read_u64(1)), "hello");
+    assert_eq!(read_str(read_u64(2)), "world");
+    assert_eq!(read_u64(3), 0);
+    assert_eq!(read_str(read_u64(4)), "HOME=/usr/admin");
+    assert_eq!(read_u64(5), 0);
+
+    // The arguments cannot take the space of the stack
+    let arg = "a".repeat(MAX_ARGS_SIZE as usize);
+    assert!(stack_layout(&[&arg], &env).is_err());
+    let args = vec!["a"; MAX_ARGS_SIZE as usize / 8];
+    assert!(stack_layout(&args, &env).is_err());
+    env.insert("PATH".to_string(), arg);
+    assert!(stack_layout(&["hello"], &env).is_err());
+}
+
+#[test_case]
+fn test_parse_elf() {
+    let (entry_point, segments) = parse_elf(include_bytes!("../../dsk/bin/hello")).unwrap();
+    assert_eq!(entry_point, CODE_ADDR + 0x100);
+    assert_eq!(segments.len(), 1);
+    assert_eq!(segments[0].addr, CODE_ADDR + 0x100);
+    assert!(segments[0].is_executable());
+
+    assert_eq!(parse_elf(b"\x7FELF").err(), Some(ElfError::Malformed));
+    let mut bin = include_bytes!("../../dsk/bin/hello").to_vec();
+    bin[18] = 0x03; // Machine: Intel 80386
+    assert_eq!(parse_elf(&bin).err(), Some(ElfError::UnsupportedArch));
 }
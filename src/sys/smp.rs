@@ -0,0 +1,217 @@
+use crate::sys;
+use crate::sys::cpu::MAX_CPUS;
+use alloc::vec;
+use core::sync::atomic::{fence, Ordering};
+use x86_64::registers::control::Cr3;
+use x86_64::structures::paging::{Mapper, Page, PageTableFlags, PhysFrame, Size4KiB, Translate};
+use x86_64::{PhysAddr, VirtAddr};
+
+// The application processors (AP) are started by the bootstrap processor
+// with an INIT IPI followed by a startup IPI giving them the page of their
+// first instruction in real mode, below 1 MB. The trampoline copied there
+// switches them directly to long mode with the page table of the kernel,
+// where this page is mapped to itself, and calls `ap_main` on their own stack.
+
+const LOW_MEMORY_LIMIT: u64 = 0x100000;
+const STACK_SIZE: usize = 32 * 1024;
+
+global_asm!(
+    ".global ap_trampoline_start",
+    ".global ap_trampoline_end",
+    ".global ap_trampoline_cr3",
+    ".global ap_trampoline_stack",
+    ".global ap_trampoline_entry",
+    ".global ap_trampoline_cpu",
+    ".code16",
+    "ap_trampoline_start:",
+    "cli",
+    "cld",
+    "mov ax, cs",
+    "mov ds, ax",
+    "mov ss, ax",
+    "mov sp, 0x1000", // Top of the page
+    "xor ebx, ebx",
+    "mov bx, ax",
+    "shl ebx, 4", // Physical address of the page
+
+    // Load a GDT with a 64-bit code segment
+    "lea eax, [ebx + ap_trampoline_gdt - ap_trampoline_start]",
+    "mov dword ptr [ap_trampoline_gdt_ptr - ap_trampoline_start + 2], eax",
+    "lgdt [ap_trampoline_gdt_ptr - ap_trampoline_start]",
+
+    // Enable PAE, load the page table, and set LME and NXE in EFER
+    "mov eax, cr4",
+    "or eax, 1 << 5",
+    "mov cr4, eax",
+    "mov eax, dword ptr [ap_trampoline_cr3 - ap_trampoline_start]",
+    "mov cr3, eax",
+    "mov ecx, 0xC0000080",
+    "rdmsr",
+    "or eax, (1 << 8) | (1 << 11)",
+    "wrmsr",
+
+    // Enable paging and protection at once, with the write protection and
+    // without the cache disabled at reset.
+    "mov eax, 0x80010011",
+    "mov cr0, eax",
+
+    // Load the 64-bit code segment with a far return
+    "mov ecx, 0x08",
+    "push ecx",
+    "lea eax, [ebx + ap_trampoline_long_mode - ap_trampoline_start]",
+    "push eax",
+    ".byte 0x66, 0xCB", // 32-bit RETF
+
+    ".code64",
+    "ap_trampoline_long_mode:",
+    "xor eax, eax",
+    "mov ds, ax",
+    "mov es, ax",
+    "mov ss, ax",
+    "mov fs, ax",
+    "mov gs, ax",
+    "mov rsp, [rip + ap_trampoline_stack]",
+    "mov rdi, [rip + ap_trampoline_cpu]", // Arg #1: CPU index
+    "call [rip + ap_trampoline_entry]",
+    "ud2",
+
+    ".balign 8",
+    "ap_trampoline_gdt:",
+    ".quad 0",
+    ".quad 0x00AF9A000000FFFF", // 64-bit code segment
+    "ap_trampoline_gdt_ptr:",
+    ".word ap_trampoline_gdt_ptr - ap_trampoline_gdt - 1",
+    ".long 0", // Physical address of the GDT
+    ".balign 8",
+    "ap_trampoline_cr3:",
+    ".quad 0",
+    "ap_trampoline_stack:",
+    ".quad 0",
+    "ap_trampoline_entry:",
+    ".quad 0",
+    "ap_trampoline_cpu:",
+    ".quad 0",
+    "ap_trampoline_end:",
+);
+
+extern "C" {
+    static ap_trampoline_start: u8;
+    static ap_trampoline_end: u8;
+    static ap_trampoline_cr3: u8;
+    static ap_trampoline_stack: u8;
+    static ap_trampoline_entry: u8;
+    static ap_trampoline_cpu: u8;
+}
+
+// Write a field of the trampoline copied at the given address
+fn write_field(addr: VirtAddr, field: &u8, value: u64) {
+    let offset = field as *const u8 as u64 - unsafe { &ap_trampoline_start as *const u8 as u64 };
+    unsafe { core::ptr::write_volatile((addr + offset).as_mut_ptr::<u64>(), value) };
+}
+
+// The trampoline enables paging while running at its physical address
+fn identity_map(frame: PhysFrame) -> Result<(), ()> {
+    let addr = frame.start_address();
+    let page = Page::<Size4KiB>::containing_address(VirtAddr::new(addr.as_u64()));
+    let mut mapper = unsafe { sys::mem::mapper(VirtAddr::new(sys::mem::PHYS_MEM_OFFSET)) };
+    match mapper.translate_addr(page.start_address()) {
+        Some(mapped_addr) if mapped_addr == addr => return Ok(()),
+        Some(_) => return Err(()),
+        None => {}
+    }
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+    let mut frame_allocator = sys::mem::frame_allocator();
+    match unsafe { mapper.map_to(page, frame, flags, &mut *frame_allocator) } {
+        Ok(mapping) => {
+            mapping.flush();
+            Ok(())
+        }
+        Err(_) => Err(()),
+    }
+}
+
+// Wait until the CPU with the given index is counted
+fn wait_for_cpu(cpu: usize, nanoseconds: u64) -> bool {
+    let step = 10_000;
+    for _ in 0..(nanoseconds / step) {
+        if sys::cpu::count() > cpu {
+            return true;
+        }
+        sys::time::nanowait(step);
+    }
+    sys::cpu::count() > cpu
+}
+
+fn start_cpu(apic_id: u32, page: u8, cpu: usize) -> bool {
+    sys::apic::send_init_ipi(apic_id);
+    sys::time::nanowait(10_000_000); // 10 ms
+    sys::apic::send_startup_ipi(apic_id, page);
+    if wait_for_cpu(cpu, 200_000) {
+        return true;
+    }
+    sys::apic::send_startup_ipi(apic_id, page); // Try again
+    wait_for_cpu(cpu, 100_000_000)
+}
+
+extern "C" fn ap_main(cpu: usize) -> ! {
+    sys::gdt::init_ap(cpu);
+    sys::idt::init();
+    sys::fpu::init();
+    sys::apic::init_lapic();
+    sys::process::idle_loop(cpu)
+}
+
+pub fn init() {
+    let ap_ids = sys::apic::application_processors();
+    if ap_ids.is_empty() {
+        return;
+    }
+
+    // The trampoline can only load a page table below 4 GB
+    let (page_table_frame, _) = Cr3::read();
+    if page_table_frame.start_address().as_u64() > u32::MAX as u64 {
+        return;
+    }
+    let frame = match sys::mem::frame_allocator().allocate_frame_below(PhysAddr::new(LOW_MEMORY_LIMIT)) {
+        Some(frame) => frame,
+        None => {
+            warning!("SMP could not allocate the trampoline\n");
+            return;
+        }
+    };
+    if identity_map(frame).is_err() {
+        warning!("SMP could not map the trampoline\n");
+        sys::mem::deallocate_frame(frame);
+        return;
+    }
+
+    // NOTE: The trampoline is left in memory in case a CPU that didn't
+    // respond in time would still start.
+    let addr = sys::mem::phys_to_virt(frame.start_address());
+    unsafe {
+        let start = &ap_trampoline_start as *const u8;
+        let len = &ap_trampoline_end as *const u8 as usize - start as usize;
+        core::ptr::copy_nonoverlapping(start, addr.as_mut_ptr::<u8>(), len);
+        write_field(addr, &ap_trampoline_cr3, page_table_frame.start_address().as_u64());
+        write_field(addr, &ap_trampoline_entry, ap_main as usize as u64);
+    }
+
+    let page = (frame.start_address().as_u64() >> 12) as u8;
+    for &apic_id in ap_ids {
+        let cpu = sys::cpu::count();
+        if cpu == MAX_CPUS {
+            break;
+        }
+        let stack = vec![0u8; STACK_SIZE].leak();
+        let stack_top = (stack.as_ptr() as u64 + STACK_SIZE as u64) & !0xF;
+        unsafe {
+            write_field(addr, &ap_trampoline_stack, stack_top);
+            write_field(addr, &ap_trampoline_cpu, cpu as u64);
+        }
+        fence(Ordering::SeqCst);
+        if !start_cpu(apic_id, page, cpu) {
+            warning!("SMP could not start CPU with LAPIC ID {}\n", apic_id);
+        }
+    }
+    log!("SMP {} CPUs\n", sys::cpu::count());
+}
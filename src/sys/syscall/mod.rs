@@ -1,15 +1,25 @@
 pub mod number;
 pub mod service;
 
+use crate::api::syscall::Error;
 use crate::sys;
 use crate::sys::fs::FileStat;
+use crate::sys::process::ProcessInfo;
+use alloc::vec::Vec;
 
 /*
  * Dispatching system calls
  */
 
-pub fn dispatcher(n: usize, arg1: usize, arg2: usize, arg3: usize) -> usize {
-    match n {
+pub fn dispatcher(n: usize, arg1: usize, arg2: usize, arg3: usize, arg4: usize) -> usize {
+    match dispatch(n, arg1, arg2, arg3, arg4) {
+        Ok(res) => res,
+        Err(err) => err as isize as usize,
+    }
+}
+
+fn dispatch(n: usize, arg1: usize, arg2: usize, arg3: usize, arg4: usize) -> Result<usize, Error> {
+    let res = match n {
         number::SLEEP => {
             service::sleep(f64::from_bits(arg1 as u64));
             0
@@ -20,33 +30,32 @@ pub fn dispatcher(n: usize, arg1: usize, arg2: usize, arg3: usize) -> usize {
         number::REALTIME => {
             service::realtime().to_bits() as usize
         }
+        number::MONOTONIC => {
+            service::monotonic() as usize
+        }
+        number::ADJTIME => {
+            service::adjtime(f64::from_bits(arg1 as u64));
+            0
+        }
         number::STAT => {
-            let ptr = sys::process::ptr_from_addr(arg1 as u64);
-            let len = arg2;
-            let path = unsafe { core::str::from_utf8_unchecked(core::slice::from_raw_parts(ptr, len)) };
-            let stat = unsafe { &mut *(arg3 as *mut FileStat) };
+            let path = string(arg1, arg2)?;
+            let stat = object_mut::<FileStat>(arg3)?;
             service::stat(path, stat) as usize
         }
         number::OPEN => {
-            let ptr = sys::process::ptr_from_addr(arg1 as u64);
-            let len = arg2;
+            let path = string(arg1, arg2)?;
             let flags = arg3;
-            let path = unsafe { core::str::from_utf8_unchecked(core::slice::from_raw_parts(ptr, len)) };
             service::open(path, flags) as usize
         }
         number::READ => {
             let handle = arg1;
-            let ptr = sys::process::ptr_from_addr(arg2 as u64);
-            let len = arg3;
-            let mut buf = unsafe { core::slice::from_raw_parts_mut(ptr, len) };
-            service::read(handle, &mut buf) as usize
+            let buf = slice_mut::<u8>(arg2, arg3)?;
+            service::read(handle, buf) as usize
         }
         number::WRITE => {
             let handle = arg1;
-            let ptr = sys::process::ptr_from_addr(arg2 as u64);
-            let len = arg3;
-            let mut buf = unsafe { core::slice::from_raw_parts_mut(ptr, len) };
-            service::write(handle, &mut buf) as usize
+            let buf = slice::<u8>(arg2, arg3)?;
+            service::write(handle, buf) as usize
         }
         number::CLOSE => {
             let handle = arg1;
@@ -54,25 +63,153 @@ pub fn dispatcher(n: usize, arg1: usize, arg2: usize, arg3: usize) -> usize {
             0
         }
         number::SPAWN => {
-            let ptr = sys::process::ptr_from_addr(arg1 as u64);
-            let len = arg2;
-            let path = unsafe { core::str::from_utf8_unchecked(core::slice::from_raw_parts(ptr, len)) };
-            service::spawn(path);
+            let path = string(arg1, arg2)?;
+            let args = slice::<&[u8]>(arg3, arg4)?.iter().map(|arg| {
+                string(arg.as_ptr() as usize, arg.len())
+            }).collect::<Result<Vec<&str>, Error>>()?;
+            service::spawn(path, &args) as usize
+        }
+        number::EXIT => {
+            service::exit(arg1);
             0
         }
+        number::PROCS => {
+            let buf = slice_mut::<ProcessInfo>(arg1, arg2)?;
+            service::procs(buf) as usize
+        }
+        number::KILL => {
+            let id = arg1;
+            service::kill(id) as usize
+        }
+        number::WAIT => {
+            let id = arg1;
+            service::wait(id) as usize
+        }
+        number::ALLOC => {
+            let size = arg1;
+            service::alloc(size) as usize
+        }
+        number::FREE => {
+            let addr = arg1 as u64;
+            let size = arg2;
+            service::free(addr, size) as usize
+        }
+        number::SEEK => {
+            let handle = arg1;
+            let offset = arg2 as isize;
+            let whence = arg3;
+            service::seek(handle, offset, whence) as usize
+        }
+        number::UNLINK => {
+            let path = string(arg1, arg2)?;
+            service::unlink(path) as usize
+        }
+        number::RENAME => {
+            let from = string(arg1, arg2)?;
+            let to = string(arg3, arg4)?;
+            service::rename(from, to) as usize
+        }
+        number::READDIR => {
+            let path = string(arg1, arg2)?;
+            let buf = slice_mut::<u8>(arg3, arg4)?;
+            service::readdir(path, buf) as usize
+        }
+        number::CHDIR => {
+            let path = string(arg1, arg2)?;
+            service::chdir(path) as usize
+        }
+        number::GETCWD => {
+            let buf = slice_mut::<u8>(arg1, arg2)?;
+            service::getcwd(buf) as usize
+        }
+        number::GETENV => {
+            let key = string(arg1, arg2)?;
+            let buf = slice_mut::<u8>(arg3, arg4)?;
+            service::getenv(key, buf) as usize
+        }
+        number::SETENV => {
+            let key = string(arg1, arg2)?;
+            let val = string(arg3, arg4)?;
+            service::setenv(key, val) as usize
+        }
+        number::DUP => {
+            let handle = arg1;
+            service::dup(handle) as usize
+        }
+        number::DUP2 => {
+            let handle = arg1;
+            let new_handle = arg2;
+            service::dup2(handle, new_handle) as usize
+        }
+        number::PIPE => {
+            let handles = object_mut::<[usize; 2]>(arg1)?;
+            service::pipe(handles) as usize
+        }
+        number::SIGNAL => {
+            let signal = arg1;
+            let handler = arg2;
+            let restorer = arg3;
+            service::signal(signal, handler, restorer) as usize
+        }
+        number::SIGRETURN => {
+            service::sigreturn() as usize
+        }
         _ => {
-            unimplemented!();
+            return Err(Error::ENOSYS);
         }
+    };
+    Ok(res)
+}
+
+// Give access to an array in the memory of the calling process after checking
+// that it is correctly aligned and mapped in its user space.
+fn slice<'a, T>(addr: usize, len: usize) -> Result<&'a [T], Error> {
+    let ptr = user_ptr::<T>(addr, len, false)?;
+    Ok(unsafe { core::slice::from_raw_parts(ptr, len) })
+}
+
+fn slice_mut<'a, T>(addr: usize, len: usize) -> Result<&'a mut [T], Error> {
+    let ptr = user_ptr::<T>(addr, len, true)?;
+    Ok(unsafe { core::slice::from_raw_parts_mut(ptr, len) })
+}
+
+fn object_mut<'a, T>(addr: usize) -> Result<&'a mut T, Error> {
+    let ptr = user_ptr::<T>(addr, 1, true)?;
+    Ok(unsafe { &mut *ptr })
+}
+
+fn string<'a>(addr: usize, len: usize) -> Result<&'a str, Error> {
+    core::str::from_utf8(slice::<u8>(addr, len)?).map_err(|_| Error::EINVAL)
+}
+
+fn user_ptr<T>(addr: usize, len: usize, writable: bool) -> Result<*mut T, Error> {
+    let ptr = sys::process::ptr_from_addr(addr as u64) as *mut T;
+    let size = len.checked_mul(core::mem::size_of::<T>()).ok_or(Error::EFAULT)?;
+    if ptr.is_null() || (ptr as usize) % core::mem::align_of::<T>() != 0 {
+        return Err(Error::EFAULT);
+    }
+    if !sys::process::is_user_range(ptr as u64, size, writable) {
+        return Err(Error::EFAULT);
     }
+    Ok(ptr)
 }
 
 /*
  * Sending system calls
  */
 
+// NOTE: User programs use the SYSCALL instruction but the kernel keeps using
+// the `int 0x80` interrupt because SYSRET can only return to user mode.
+
 #[doc(hidden)]
 pub unsafe fn syscall0(n: usize) -> usize {
     let res: usize;
+    #[cfg(feature = "userspace")]
+    asm!(
+        "syscall", in("rax") n,
+        lateout("rax") res, lateout("rcx") _, lateout("r11") _
+    );
+    #[cfg(not(feature = "userspace"))]
     asm!(
         "int 0x80", in("rax") n,
         lateout("rax") res
@@ -83,6 +220,13 @@ pub unsafe fn syscall0(n: usize) -> usize {
 #[doc(hidden)]
 pub unsafe fn syscall1(n: usize, arg1: usize) -> usize {
     let res: usize;
+    #[cfg(feature = "userspace")]
+    asm!(
+        "syscall", in("rax") n,
+        in("rdi") arg1,
+        lateout("rax") res, lateout("rcx") _, lateout("r11") _
+    );
+    #[cfg(not(feature = "userspace"))]
     asm!(
         "int 0x80", in("rax") n,
         in("rdi") arg1,
@@ -94,6 +238,13 @@ pub unsafe fn syscall1(n: usize, arg1: usize) -> usize {
 #[doc(hidden)]
 pub unsafe fn syscall2(n: usize, arg1: usize, arg2: usize) -> usize {
     let res: usize;
+    #[cfg(feature = "userspace")]
+    asm!(
+        "syscall", in("rax") n,
+        in("rdi") arg1, in("rsi") arg2,
+        lateout("rax") res, lateout("rcx") _, lateout("r11") _
+    );
+    #[cfg(not(feature = "userspace"))]
     asm!(
         "int 0x80", in("rax") n,
         in("rdi") arg1, in("rsi") arg2,
@@ -105,6 +256,13 @@ pub unsafe fn syscall2(n: usize, arg1: usize, arg2: usize) -> usize {
 #[doc(hidden)]
 pub unsafe fn syscall3(n: usize, arg1: usize, arg2: usize, arg3: usize) -> usize {
     let res: usize;
+    #[cfg(feature = "userspace")]
+    asm!(
+        "syscall", in("rax") n,
+        in("rdi") arg1, in("rsi") arg2, in("rdx") arg3,
+        lateout("rax") res, lateout("rcx") _, lateout("r11") _
+    );
+    #[cfg(not(feature = "userspace"))]
     asm!(
         "int 0x80", in("rax") n,
         in("rdi") arg1, in("rsi") arg2, in("rdx") arg3,
@@ -113,6 +271,24 @@ pub unsafe fn syscall3(n: usize, arg1: usize, arg2: usize, arg3: usize) -> usize
     res
 }
 
+#[doc(hidden)]
+pub unsafe fn syscall4(n: usize, arg1: usize, arg2: usize, arg3: usize, arg4: usize) -> usize {
+    let res: usize;
+    #[cfg(feature = "userspace")]
+    asm!(
+        "syscall", in("rax") n,
+        in("rdi") arg1, in("rsi") arg2, in("rdx") arg3, in("r10") arg4,
+        lateout("rax") res, lateout("rcx") _, lateout("r11") _
+    );
+    #[cfg(not(feature = "userspace"))]
+    asm!(
+        "int 0x80", in("rax") n,
+        in("rdi") arg1, in("rsi") arg2, in("rdx") arg3, in("r10") arg4,
+        lateout("rax") res
+    );
+    res
+}
+
 #[macro_export]
 macro_rules! syscall {
     ($n:expr) => (
@@ -127,4 +303,29 @@ macro_rules! syscall {
     ($n:expr, $a1:expr, $a2:expr, $a3:expr) => (
         $crate::sys::syscall::syscall3(
             $n as usize, $a1 as usize, $a2 as usize, $a3 as usize));
+    ($n:expr, $a1:expr, $a2:expr, $a3:expr, $a4:expr) => (
+        $crate::sys::syscall::syscall4(
+            $n as usize, $a1 as usize, $a2 as usize, $a3 as usize, $a4 as usize));
+}
+
+#[test_case]
+fn test_dispatcher_errors() {
+    let res = dispatcher(0xFFFF, 0, 0, 0, 0) as isize;
+    assert_eq!(res, Error::ENOSYS as isize);
+
+    let path = [0xFF, 0xFE];
+    let mut stat = FileStat::new();
+    let stat_ptr = &mut stat as *mut FileStat as usize;
+    let res = dispatcher(number::STAT, path.as_ptr() as usize, path.len(), stat_ptr, 0) as isize;
+    assert_eq!(res, Error::EINVAL as isize);
+
+    // The kernel doesn't allocate memory with syscalls
+    let res = dispatcher(number::ALLOC, 4096, 0, 0, 0) as isize;
+    assert_eq!(res, Error::ENOMEM as isize);
+    let res = dispatcher(number::FREE, 0x6000_0000, 4096, 0, 0) as isize;
+    assert_eq!(res, Error::EINVAL as isize);
+
+    let key = "A=B";
+    let res = dispatcher(number::SETENV, key.as_ptr() as usize, key.len(), key.as_ptr() as usize, 0) as isize;
+    assert_eq!(res, Error::EINVAL as isize);
 }
@@ -1,9 +1,30 @@
-pub const SLEEP:    usize = 0;
-pub const UPTIME:   usize = 1;
-pub const REALTIME: usize = 2;
-pub const OPEN:     usize = 3;
-pub const READ:     usize = 4;
-pub const WRITE:    usize = 5;
-pub const CLOSE:    usize = 6;
-pub const STAT:     usize = 7;
-pub const SPAWN:    usize = 8;
+pub const SLEEP:     usize = 0;
+pub const UPTIME:    usize = 1;
+pub const REALTIME:  usize = 2;
+pub const OPEN:      usize = 3;
+pub const READ:      usize = 4;
+pub const WRITE:     usize = 5;
+pub const CLOSE:     usize = 6;
+pub const STAT:      usize = 7;
+pub const SPAWN:     usize = 8;
+pub const EXIT:      usize = 9;
+pub const PROCS:     usize = 10;
+pub const KILL:      usize = 11;
+pub const WAIT:      usize = 12;
+pub const ALLOC:     usize = 13;
+pub const FREE:      usize = 14;
+pub const SEEK:      usize = 15;
+pub const UNLINK:    usize = 16;
+pub const RENAME:    usize = 17;
+pub const READDIR:   usize = 18;
+pub const CHDIR:     usize = 19;
+pub const GETCWD:    usize = 20;
+pub const GETENV:    usize = 21;
+pub const SETENV:    usize = 22;
+pub const DUP:       usize = 23;
+pub const DUP2:      usize = 24;
+pub const PIPE:      usize = 25;
+pub const SIGNAL:    usize = 26;
+pub const SIGRETURN: usize = 27;
+pub const MONOTONIC: usize = 28;
+pub const ADJTIME:   usize = 29;
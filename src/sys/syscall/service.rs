@@ -1,8 +1,12 @@
+use crate::api::syscall::Error;
 use crate::sys;
 use crate::sys::fs::FileStat;
 use crate::sys::fs::FileIO;
-use crate::sys::process::Process;
+use crate::sys::fs::{Resource, SeekFrom};
+use crate::sys::pipe::Pipe;
+use crate::sys::process::{Process, ProcessInfo};
 use alloc::vec;
+use alloc::vec::Vec;
 
 pub fn sleep(seconds: f64) {
     sys::time::sleep(seconds);
@@ -16,58 +20,265 @@ pub fn realtime() -> f64 {
     sys::clock::realtime()
 }
 
+pub fn monotonic() -> u64 {
+    sys::clock::monotonic()
+}
+
+pub fn adjtime(seconds: f64) {
+    sys::clock::adjust_realtime(seconds)
+}
+
 pub fn stat(path: &str, stat: &mut FileStat) -> isize {
     if let Some(res) = sys::fs::stat(path) {
         *stat = res;
         0
     } else {
-        -1
+        Error::ENOENT as isize
     }
 }
 
 pub fn open(path: &str, flags: usize) -> isize {
     if let Some(resource) = sys::fs::open(path, flags) {
         if let Ok(handle) = sys::process::create_file_handle(resource) {
-            return handle as isize;
+            handle as isize
+        } else {
+            Error::EMFILE as isize
         }
+    } else {
+        Error::ENOENT as isize
     }
-    -1
 }
 
 pub fn read(handle: usize, buf: &mut [u8]) -> isize {
     if let Some(mut file) = sys::process::file_handle(handle) {
         if let Ok(bytes) = file.read(buf) {
             sys::process::update_file_handle(handle, file);
-            return bytes as isize;
+            bytes as isize
+        } else {
+            Error::EIO as isize
         }
+    } else {
+        Error::EBADF as isize
     }
-    -1
 }
 
-pub fn write(handle: usize, buf: &mut [u8]) -> isize {
+pub fn write(handle: usize, buf: &[u8]) -> isize {
     if let Some(mut file) = sys::process::file_handle(handle) {
         if let Ok(bytes) = file.write(buf) {
             sys::process::update_file_handle(handle, file);
-            return bytes as isize;
+            bytes as isize
+        } else {
+            Error::EIO as isize
+        }
+    } else {
+        Error::EBADF as isize
+    }
+}
+
+pub fn seek(handle: usize, offset: isize, whence: usize) -> isize {
+    let pos = match whence {
+        0 if offset >= 0 => SeekFrom::Start(offset as u32),
+        1 => SeekFrom::Current(offset as i32),
+        2 => SeekFrom::End(offset as i32),
+        _ => return Error::EINVAL as isize,
+    };
+    match sys::process::file_handle(handle) {
+        Some(Resource::File(mut file)) => {
+            if let Ok(offset) = file.seek(pos) {
+                sys::process::update_file_handle(handle, Resource::File(file));
+                offset as isize
+            } else {
+                Error::EINVAL as isize
+            }
+        }
+        Some(_) => Error::ESPIPE as isize,
+        None => Error::EBADF as isize,
+    }
+}
+
+pub fn unlink(path: &str) -> isize {
+    if let Some(stat) = sys::fs::stat(path) {
+        if sys::fs::delete(path).is_ok() {
+            0
+        } else if stat.is_dir() {
+            Error::ENOTEMPTY as isize
+        } else {
+            Error::EIO as isize
+        }
+    } else {
+        Error::ENOENT as isize
+    }
+}
+
+pub fn rename(from: &str, to: &str) -> isize {
+    if sys::fs::stat(from).is_none() {
+        Error::ENOENT as isize
+    } else if sys::fs::stat(to).is_some() {
+        Error::EEXIST as isize
+    } else if sys::fs::rename(from, to).is_ok() {
+        0
+    } else {
+        Error::EINVAL as isize
+    }
+}
+
+pub fn readdir(path: &str, buf: &mut [u8]) -> isize {
+    if let Some(files) = sys::fs::read_dir(path) {
+        let data: Vec<u8> = files.iter().flat_map(|file| file.as_bytes()).collect();
+        copy_bytes(&data, buf)
+    } else {
+        Error::ENOENT as isize
+    }
+}
+
+pub fn chdir(path: &str) -> isize {
+    let mut path = sys::fs::realpath(path);
+    if path.len() > 1 {
+        path = path.trim_end_matches('/').into();
+    }
+    if sys::fs::Dir::open(&path).is_some() {
+        sys::process::set_dir(&path);
+        0
+    } else {
+        Error::ENOENT as isize
+    }
+}
+
+pub fn getcwd(buf: &mut [u8]) -> isize {
+    copy_bytes(sys::process::dir().as_bytes(), buf)
+}
+
+pub fn getenv(key: &str, buf: &mut [u8]) -> isize {
+    if let Some(val) = sys::process::env(key) {
+        copy_bytes(val.as_bytes(), buf)
+    } else {
+        Error::ENOENT as isize
+    }
+}
+
+pub fn setenv(key: &str, val: &str) -> isize {
+    if key.is_empty() || key.contains('=') {
+        return Error::EINVAL as isize;
+    }
+    sys::process::set_env(key, val);
+    0
+}
+
+// Copy as much data as possible into the buffer and return the size of the
+// data to let the caller retry with a bigger buffer.
+fn copy_bytes(data: &[u8], buf: &mut [u8]) -> isize {
+    let n = data.len().min(buf.len());
+    buf[0..n].copy_from_slice(&data[0..n]);
+    data.len() as isize
+}
+
+pub fn dup(handle: usize) -> isize {
+    if sys::process::file_handle(handle).is_none() {
+        Error::EBADF as isize
+    } else if let Ok(new_handle) = sys::process::dup(handle) {
+        new_handle as isize
+    } else {
+        Error::EMFILE as isize
+    }
+}
+
+pub fn dup2(handle: usize, new_handle: usize) -> isize {
+    if let Ok(new_handle) = sys::process::dup2(handle, new_handle) {
+        new_handle as isize
+    } else {
+        Error::EBADF as isize
+    }
+}
+
+// Create a pipe and give the handles of its read end and its write end
+pub fn pipe(handles: &mut [usize; 2]) -> isize {
+    let (reader, writer) = Pipe::new();
+    if let Ok(reader) = sys::process::create_file_handle(Resource::Pipe(reader)) {
+        if let Ok(writer) = sys::process::create_file_handle(Resource::Pipe(writer)) {
+            *handles = [reader, writer];
+            return 0;
         }
+        sys::process::delete_file_handle(reader);
+    }
+    Error::EMFILE as isize
+}
+
+// Register the handler called when the given signal is received, or restore
+// the default action with a null handler.
+pub fn signal(signal: usize, handler: usize, restorer: usize) -> isize {
+    if sys::process::set_signal_handler(signal, handler, restorer).is_ok() {
+        0
+    } else {
+        Error::EINVAL as isize
+    }
+}
+
+// The context interrupted by the signal will be restored by the syscall
+// handler after this call.
+pub fn sigreturn() -> isize {
+    if sys::process::has_signal_context() {
+        0
+    } else {
+        Error::EINVAL as isize
     }
-    -1
 }
 
 pub fn close(handle: usize) {
     sys::process::delete_file_handle(handle);
 }
 
-pub fn spawn(path: &str) -> isize {
+pub fn spawn(path: &str, args: &[&str]) -> isize {
     if let Some(mut file) = sys::fs::File::open(path) {
         let mut buf = vec![0; file.size()];
         if let Ok(bytes) = file.read(&mut buf) {
             buf.resize(bytes, 0);
-            if let Ok(process) = Process::create(&buf) {
-                process.exec();
-                return 0;
+            if let Ok(id) = Process::spawn(&buf, args) {
+                id as isize
+            } else {
+                Error::ENOEXEC as isize
             }
+        } else {
+            Error::EIO as isize
         }
+    } else {
+        Error::ENOENT as isize
+    }
+}
+
+pub fn exit(code: usize) {
+    sys::process::exit(code);
+}
+
+pub fn procs(buf: &mut [ProcessInfo]) -> isize {
+    sys::process::list(buf) as isize
+}
+
+pub fn wait(id: usize) -> isize {
+    match sys::process::wait(id) {
+        Ok(code) => code as isize,
+        Err(()) => Error::ECHILD as isize,
+    }
+}
+
+pub fn alloc(size: usize) -> isize {
+    match sys::process::alloc(size) {
+        Ok(addr) => addr as isize,
+        Err(()) => Error::ENOMEM as isize,
+    }
+}
+
+pub fn free(addr: u64, size: usize) -> isize {
+    if sys::process::free(addr, size).is_ok() {
+        0
+    } else {
+        Error::EINVAL as isize
+    }
+}
+
+pub fn kill(id: usize) -> isize {
+    if sys::process::kill(id).is_ok() {
+        0
+    } else {
+        Error::ESRCH as isize
     }
-    -1
 }
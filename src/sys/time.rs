@@ -1,5 +1,7 @@
 use crate::sys;
+use crate::sys::acpi::MorosAcpiHandler;
 use crate::sys::cmos::CMOS;
+use acpi::{AcpiTables, HpetInfo};
 use core::hint::spin_loop;
 use core::sync::atomic::{AtomicUsize, AtomicU64, Ordering};
 use x86_64::instructions::interrupts;
@@ -13,9 +15,22 @@ pub const PIT_FREQUENCY: f64 = 3_579_545.0 / 3.0; // 1_193_181.666 Hz
 const PIT_DIVIDER: usize = 1193;
 const PIT_INTERVAL: f64 = (PIT_DIVIDER as f64) / PIT_FREQUENCY;
 
+// The monotonic clock is given by the TSC, calibrated at boot with the PIT
+// and again with the HPET if there is one. Its frequency is assumed to be
+// invariant and the same on every CPU, which is the case on modern CPUs.
+const PIT_CALIBRATION_TICKS: usize = 100;
+const HPET_CALIBRATION_TIME: u64 = 10_000_000; // 10 ms
+
+// HPET registers
+const HPET_CAPABILITIES: u64 = 0x000;
+const HPET_CONFIG: u64 = 0x010;
+const HPET_COUNTER: u64 = 0x0F0;
+const HPET_ENABLE: u64 = 1;
+
 static PIT_TICKS: AtomicUsize = AtomicUsize::new(0);
-static LAST_RTC_UPDATE: AtomicUsize = AtomicUsize::new(0);
-static CLOCKS_PER_NANOSECOND: AtomicU64 = AtomicU64::new(0);
+static LAST_RTC_UPDATE: AtomicU64 = AtomicU64::new(0);
+static TSC_FREQUENCY: AtomicU64 = AtomicU64::new(0); // Hz
+static TSC_START: AtomicU64 = AtomicU64::new(0); // TSC at the start of the clock
 
 pub fn ticks() -> usize {
     PIT_TICKS.load(Ordering::Relaxed)
@@ -25,10 +40,16 @@ pub fn time_between_ticks() -> f64 {
     PIT_INTERVAL
 }
 
-pub fn last_rtc_update() -> usize {
+// Time of the last RTC update in nanoseconds since boot
+pub fn last_rtc_update() -> u64 {
     LAST_RTC_UPDATE.load(Ordering::Relaxed)
 }
 
+// Restart the fraction of second added to the RTC when it has been set
+pub fn reset_last_rtc_update() {
+    LAST_RTC_UPDATE.store(nanoseconds(), Ordering::Relaxed);
+}
+
 pub fn halt() {
     let disabled = !interrupts::are_enabled();
     interrupts::enable_and_hlt();
@@ -37,6 +58,14 @@ pub fn halt() {
     }
 }
 
+// Halt the CPU and let the scheduler switch to another process in the
+// meantime, which must only be done by a process waiting without any lock.
+pub fn halt_preemptible() {
+    sys::process::set_idle(true);
+    halt();
+    sys::process::set_idle(false);
+}
+
 fn rdtsc() -> u64 {
     unsafe {
         core::arch::x86_64::_mm_lfence();
@@ -44,16 +73,55 @@ fn rdtsc() -> u64 {
     }
 }
 
+pub fn tsc_frequency() -> u64 {
+    TSC_FREQUENCY.load(Ordering::SeqCst)
+}
+
+// Nanoseconds since the PIT was initialized, counted with its ticks until
+// the TSC is calibrated.
+pub fn nanoseconds() -> u64 {
+    let frequency = tsc_frequency();
+    if frequency == 0 {
+        return (ticks() as f64 * PIT_INTERVAL * 1e9) as u64;
+    }
+    let cycles = rdtsc().saturating_sub(TSC_START.load(Ordering::SeqCst));
+    (cycles as u128 * 1_000_000_000 / frequency as u128) as u64
+}
+
+// Change the frequency of the TSC without changing the current time, so the
+// clock stays monotonic across calibrations.
+fn set_tsc_frequency(frequency: u64) {
+    interrupts::without_interrupts(|| {
+        let elapsed = nanoseconds() as u128 * frequency as u128 / 1_000_000_000;
+        TSC_START.store(rdtsc().saturating_sub(elapsed as u64), Ordering::SeqCst);
+        TSC_FREQUENCY.store(frequency, Ordering::SeqCst);
+    });
+}
+
+// Halt the CPU until the next tick and finish the sleep by spinning when
+// there is less than a tick left.
 pub fn sleep(seconds: f64) {
-    let start = sys::clock::uptime();
-    while sys::clock::uptime() - start < seconds {
-        halt();
+    let end = nanoseconds().saturating_add((seconds * 1e9) as u64);
+    let tick = (PIT_INTERVAL * 1e9) as u64;
+    loop {
+        if sys::process::has_pending_signal() {
+            break; // Let the signal be delivered
+        }
+        let now = nanoseconds();
+        if now >= end {
+            break;
+        }
+        if end - now < tick && tsc_frequency() > 0 {
+            nanowait(end - now);
+            break;
+        }
+        halt_preemptible();
     }
 }
 
 pub fn nanowait(nanoseconds: u64) {
     let start = rdtsc();
-    let delta = nanoseconds * CLOCKS_PER_NANOSECOND.load(Ordering::Relaxed);
+    let delta = (nanoseconds as u128 * tsc_frequency() as u128 / 1_000_000_000) as u64;
     while rdtsc() - start < delta {
         spin_loop();
     }
@@ -80,7 +148,7 @@ pub fn pit_interrupt_handler() {
 }
 
 pub fn rtc_interrupt_handler() {
-    LAST_RTC_UPDATE.store(ticks(), Ordering::Relaxed);
+    LAST_RTC_UPDATE.store(nanoseconds(), Ordering::Relaxed);
     CMOS::new().notify_end_of_interrupt();
 }
 
@@ -96,9 +164,58 @@ pub fn init() {
     CMOS::new().enable_update_interrupt();
 
     // TSC timmer
-    let calibration_time = 250_000; // 0.25 seconds
+    set_tsc_frequency(calibrate_tsc_with_pit());
+}
+
+// Count the cycles of the TSC during a few ticks of the PIT
+fn calibrate_tsc_with_pit() -> u64 {
+    let start = ticks() + 1;
+    while ticks() < start {
+        halt();
+    }
     let a = rdtsc();
-    sleep(calibration_time as f64 / 1e6);
+    while ticks() < start + PIT_CALIBRATION_TICKS {
+        halt();
+    }
     let b = rdtsc();
-    CLOCKS_PER_NANOSECOND.store((b - a) / calibration_time, Ordering::Relaxed);
+    ((b - a) as f64 / (PIT_CALIBRATION_TICKS as f64 * PIT_INTERVAL)) as u64
+}
+
+// Count the cycles of the TSC during a few milliseconds of the HPET counter
+fn calibrate_tsc_with_hpet(addr: u64) -> Option<u64> {
+    let read = |reg: u64| unsafe { core::ptr::read_volatile((addr + reg) as *const u64) };
+    let write = |reg: u64, value: u64| unsafe { core::ptr::write_volatile((addr + reg) as *mut u64, value) };
+
+    let period = read(HPET_CAPABILITIES) >> 32; // Femtoseconds per tick
+    if period == 0 || period > 100_000_000 {
+        return None;
+    }
+    write(HPET_CONFIG, read(HPET_CONFIG) | HPET_ENABLE);
+    let hpet_ticks = HPET_CALIBRATION_TIME * 1_000_000 / period;
+    interrupts::without_interrupts(|| {
+        let start = read(HPET_COUNTER);
+        let a = rdtsc();
+        let mut elapsed = 0;
+        while elapsed < hpet_ticks {
+            spin_loop();
+            elapsed = read(HPET_COUNTER).wrapping_sub(start);
+        }
+        let b = rdtsc();
+        let femtoseconds = elapsed as u128 * period as u128;
+        Some(((b - a) as u128 * 1_000_000_000_000_000 / femtoseconds) as u64)
+    })
+}
+
+// Calibrate the TSC again with the HPET described by ACPI, which is more
+// precise than the PIT.
+pub fn calibrate() {
+    let tables = unsafe { AcpiTables::search_for_rsdp_bios(MorosAcpiHandler) };
+    if let Ok(hpet) = tables.and_then(|tables| HpetInfo::new(&tables)) {
+        log!("HPET at {:#X}\n", hpet.base_address);
+        let addr = sys::mem::map_registers(hpet.base_address as u64);
+        if let Some(frequency) = calibrate_tsc_with_hpet(addr) {
+            set_tsc_frequency(frequency);
+        }
+    }
+    log!("TSC {} MHz\n", tsc_frequency() / 1_000_000);
 }
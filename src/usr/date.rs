@@ -1,8 +1,18 @@
-use crate::{sys, usr};
+use crate::{api, usr};
 use crate::api::syscall;
-use time::{OffsetDateTime, Duration, UtcOffset};
+use alloc::format;
+use alloc::string::String;
+use time::{OffsetDateTime, PrimitiveDateTime, Duration, UtcOffset};
 
 pub fn main(args: &[&str]) -> usr::shell::ExitCode {
+    if args.len() > 1 && args[1] == "set" {
+        if args.len() != 3 {
+            println!("Usage: date set <YYYY-MM-DDTHH:MM:SS[Z|+HH:MM]>");
+            return usr::shell::ExitCode::CommandError;
+        }
+        return set(args[2]);
+    }
+
     let seconds = syscall::realtime(); // Since Unix Epoch
     let nanoseconds = libm::floor(1e9 * (seconds - libm::floor(seconds))) as i64;
     let date = OffsetDateTime::from_unix_timestamp(seconds as i64).to_offset(offset())
@@ -21,8 +31,49 @@ pub fn main(args: &[&str]) -> usr::shell::ExitCode {
     }
 }
 
+// Write the given date in UTC to the RTC
+fn set(arg: &str) -> usr::shell::ExitCode {
+    let date = match parse(arg) {
+        Some(date) => date.to_offset(UtcOffset::UTC),
+        None => {
+            println!("Error: could not parse date '{}'", arg);
+            return usr::shell::ExitCode::CommandError;
+        }
+    };
+    let pathname = "/dev/rtc";
+    if let Some(handle) = api::fs::open_device(pathname) {
+        let buf = date.format("%FT%H:%M:%S");
+        let res = syscall::write(handle, buf.as_bytes());
+        syscall::close(handle);
+        if res.is_ok() {
+            return usr::shell::ExitCode::CommandSuccessful;
+        }
+    }
+    println!("Could not write to '{}'", pathname);
+    usr::shell::ExitCode::CommandError
+}
+
+// Parse an ISO 8601 date like "2001-08-23T14:55:02" in the timezone given by
+// TZ, or followed by "Z" for UTC, or by an offset like "+01:00" or "+0100".
+fn parse(s: &str) -> Option<OffsetDateTime> {
+    let format = "%FT%H:%M:%S";
+    if let Some(s) = s.strip_suffix('Z') {
+        return PrimitiveDateTime::parse(s, format).ok().map(|date| date.assume_utc());
+    }
+    if let Ok(date) = PrimitiveDateTime::parse(s, format) {
+        return Some(date.assume_offset(offset()));
+    }
+    let n = s.len();
+    let s: String = if n > 3 && s.is_char_boundary(n - 3) && s[(n - 3)..].starts_with(':') {
+        format!("{}{}", &s[0..(n - 3)], &s[(n - 2)..])
+    } else {
+        s.into()
+    };
+    OffsetDateTime::parse(&s, &format!("{}%z", format)).ok()
+}
+
 fn offset() -> UtcOffset {
-    if let Some(tz) = sys::process::env("TZ") {
+    if let Some(tz) = api::process::env("TZ") {
         if let Ok(offset) = tz.parse::<i32>() {
             return UtcOffset::seconds(offset);
         }
@@ -1,4 +1,4 @@
-use crate::{sys, usr};
+use crate::usr;
 use crate::api::fs;
 
 pub fn main(args: &[&str]) -> usr::shell::ExitCode {
@@ -19,9 +19,9 @@ pub fn main(args: &[&str]) -> usr::shell::ExitCode {
         pathname = pathname.trim_end_matches('/');
     }
 
-    if let Some(dir) = sys::fs::Dir::open(pathname) {
-        if dir.entries().count() == 0 {
-            if sys::fs::Dir::delete(pathname).is_ok() {
+    if let Ok(files) = fs::read_dir(pathname) {
+        if files.is_empty() {
+            if fs::delete(pathname).is_ok() {
                 usr::shell::ExitCode::CommandSuccessful
             } else {
                 println!("Could not delete directory '{}'", pathname);
@@ -32,7 +32,7 @@ pub fn main(args: &[&str]) -> usr::shell::ExitCode {
             usr::shell::ExitCode::CommandError
         }
     } else if fs::exists(pathname) {
-        if sys::fs::File::delete(pathname).is_ok() {
+        if fs::delete(pathname).is_ok() {
             usr::shell::ExitCode::CommandSuccessful
         } else {
             println!("Could not delete file '{}'", pathname);
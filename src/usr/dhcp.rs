@@ -1,90 +1,263 @@
 use crate::{sys, usr};
 use crate::api::syscall;
+use crate::api::random;
 use alloc::string::ToString;
 use alloc::vec;
 use alloc::vec::Vec;
 use core::time::Duration;
+use lazy_static::lazy_static;
 use smoltcp::dhcp::Dhcpv4Client;
 use smoltcp::socket::{RawPacketMetadata, RawSocketBuffer, SocketSet};
-use smoltcp::time::Instant;
-use smoltcp::wire::{IpCidr, Ipv4Address, Ipv4Cidr};
+use smoltcp::socket::{UdpPacketMetadata, UdpSocket, UdpSocketBuffer};
+use smoltcp::wire::{EthernetAddress, IpAddress, IpCidr, IpEndpoint, Ipv4Address, Ipv4Cidr};
+use spin::Mutex;
 
-pub fn main(_args: &[&str]) -> usr::shell::ExitCode {
-    if let Some(ref mut iface) = *sys::net::IFACE.lock() {
-        let mut iface = iface;
-        let mut sockets = SocketSet::new(vec![]);
-        let dhcp_rx_buffer = RawSocketBuffer::new([RawPacketMetadata::EMPTY; 1], vec![0; 900]);
-        let dhcp_tx_buffer = RawSocketBuffer::new([RawPacketMetadata::EMPTY; 1], vec![0; 600]);
+// See RFC 2131 and RFC 2132 for implementation details
 
-        let timestamp = Instant::from_millis((syscall::realtime() * 1000.0) as i64);
-        let mut dhcp = Dhcpv4Client::new(&mut sockets, dhcp_rx_buffer, dhcp_tx_buffer, timestamp);
+const SERVER_PORT: u16 = 67;
+const CLIENT_PORT: u16 = 68;
+const MAGIC_COOKIE: [u8; 4] = [99, 130, 83, 99];
 
-        let prev_cidr = match iface.ip_addrs().first() {
-            Some(IpCidr::Ipv4(ip_addr)) => *ip_addr,
-            _ => Ipv4Cidr::new(Ipv4Address::UNSPECIFIED, 0),
-        };
+const OPTION_PAD: u8 = 0;
+const OPTION_NTP_SERVERS: u8 = 42;
+const OPTION_MESSAGE_TYPE: u8 = 53;
+const OPTION_PARAMETER_REQUEST_LIST: u8 = 55;
+const OPTION_END: u8 = 255;
+
+const MESSAGE_ACK: u8 = 5;
+const MESSAGE_INFORM: u8 = 8;
+
+lazy_static! {
+    static ref NTP_SERVERS: Mutex<Vec<Ipv4Address>> = Mutex::new(Vec::new());
+}
 
-        println!("DHCP Discover transmitted");
-        let timeout = 30.0;
-        let started = syscall::realtime();
-        loop {
-            if syscall::realtime() - started > timeout {
-                println!("Timeout reached");
-                return usr::shell::ExitCode::CommandError;
+// NTP servers given by the DHCP server the last time the network was
+// configured with the `dhcp` command.
+pub fn ntp_servers() -> Vec<Ipv4Address> {
+    NTP_SERVERS.lock().clone()
+}
+
+// The DHCP client of smoltcp doesn't request the NTP servers option, so it is
+// requested afterward with a DHCPINFORM message, that is answered by a DHCPACK
+// without leasing the address again.
+fn inform_request(xid: u32, ip_addr: Ipv4Address, eth_addr: EthernetAddress) -> Vec<u8> {
+    let mut datagram = vec![0; 300];
+    datagram[0] = 1; // Boot request
+    datagram[1] = 1; // Ethernet
+    datagram[2] = 6; // Hardware address length
+    datagram[4..8].copy_from_slice(&xid.to_be_bytes());
+    datagram[12..16].copy_from_slice(ip_addr.as_bytes());
+    datagram[28..34].copy_from_slice(eth_addr.as_bytes());
+    datagram[236..240].copy_from_slice(&MAGIC_COOKIE);
+    datagram[240..247].copy_from_slice(&[
+        OPTION_MESSAGE_TYPE, 1, MESSAGE_INFORM,
+        OPTION_PARAMETER_REQUEST_LIST, 1, OPTION_NTP_SERVERS,
+        OPTION_END,
+    ]);
+    datagram
+}
+
+// Parse the NTP servers of a DHCPACK answering the request with the given ID
+fn parse_ntp_servers(xid: u32, datagram: &[u8]) -> Option<Vec<Ipv4Address>> {
+    if datagram.len() < 240 || datagram[0] != 2 || datagram[4..8] != xid.to_be_bytes() || datagram[236..240] != MAGIC_COOKIE {
+        return None;
+    }
+    let mut is_ack = false;
+    let mut servers = Vec::new();
+    let mut i = 240;
+    while i < datagram.len() {
+        match datagram[i] {
+            OPTION_END => break,
+            OPTION_PAD => i += 1,
+            kind => {
+                let len = *datagram.get(i + 1)? as usize;
+                let data = datagram.get((i + 2)..(i + 2 + len))?;
+                match kind {
+                    OPTION_MESSAGE_TYPE => is_ack = data == [MESSAGE_ACK],
+                    OPTION_NTP_SERVERS => servers = data.chunks_exact(4).map(Ipv4Address::from_bytes).collect(),
+                    _ => {}
+                }
+                i += 2 + len;
             }
-            if sys::console::end_of_text() {
-                println!();
-                return usr::shell::ExitCode::CommandError;
+        }
+    }
+    if is_ack { Some(servers) } else { None }
+}
+
+fn request_ntp_servers(ip_addr: Ipv4Address) -> Option<Vec<Ipv4Address>> {
+    let client = IpEndpoint::new(IpAddress::Unspecified, CLIENT_PORT);
+    let server = IpEndpoint::new(IpAddress::Ipv4(Ipv4Address::BROADCAST), SERVER_PORT);
+    let xid = random::get_u64() as u32;
+
+    let udp_rx_buffer = UdpSocketBuffer::new(vec![UdpPacketMetadata::EMPTY], vec![0; 900]);
+    let udp_tx_buffer = UdpSocketBuffer::new(vec![UdpPacketMetadata::EMPTY], vec![0; 600]);
+    let udp_socket = UdpSocket::new(udp_rx_buffer, udp_tx_buffer);
+
+    let mut sockets = SocketSet::new(vec![]);
+    let udp_handle = sockets.add(udp_socket);
+
+    enum State { Bind, Request, Response }
+    let mut state = State::Bind;
+    let timeout = 2.0;
+    let started = syscall::uptime();
+    loop {
+        if syscall::uptime() - started > timeout || sys::console::end_of_text() {
+            return None; // Not every DHCP server answers a DHCPINFORM
+        }
+        let mut iface_lock = sys::net::IFACE.lock();
+        let iface = iface_lock.as_mut()?;
+        let timestamp = sys::net::time();
+        match iface.poll(&mut sockets, timestamp) {
+            Err(smoltcp::Error::Unrecognized) => {}
+            Err(e) => {
+                println!("Network Error: {}", e);
             }
-            let timestamp = Instant::from_millis((syscall::realtime() * 1000.0) as i64);
-            match iface.poll(&mut sockets, timestamp) {
-                Err(smoltcp::Error::Unrecognized) => {}
-                Err(e) => {
-                    println!("Network Error: {}", e);
+            Ok(_) => {}
+        }
+
+        {
+            let mut socket = sockets.get::<UdpSocket>(udp_handle);
+
+            state = match state {
+                State::Bind if !socket.is_open() => {
+                    socket.bind(client).ok()?;
+                    State::Request
                 }
-                Ok(_) => {}
-            }
-            let res = dhcp.poll(&mut iface, &mut sockets, timestamp).unwrap_or_else(|e| {
-                println!("DHCP Error: {:?}", e);
-                None
-            });
-            if let Some(config) = res {
-                println!("DHCP Offer received");
-                if let Some(cidr) = config.address {
-                    if cidr != prev_cidr {
-                        iface.update_ip_addrs(|addrs| {
-                            if let Some(addr) = addrs.iter_mut().next() {
-                                *addr = IpCidr::Ipv4(cidr);
-                            }
-                        });
-                        println!("Leased: {}", cidr);
+                State::Request if socket.can_send() => {
+                    let request = inform_request(xid, ip_addr, iface.ethernet_addr());
+                    socket.send_slice(&request, server).ok()?;
+                    State::Response
+                }
+                State::Response if socket.can_recv() => {
+                    let (data, _) = socket.recv().ok()?;
+                    if let Some(servers) = parse_ntp_servers(xid, data) {
+                        return Some(servers);
                     }
+                    state
                 }
+                _ => state
+            }
+        }
 
-                config.router.map(|router| {
-                    iface.routes_mut().add_default_ipv4_route(router).unwrap()
-                });
-                iface.routes_mut().update(|routes_map| {
-                    let unspecified = IpCidr::new(Ipv4Address::UNSPECIFIED.into(), 0);
-                    if let Some(default_route) = routes_map.get(&unspecified) {
-                        println!("Router: {}", default_route.via_router);
-                    }
-                });
+        let wait_duration = iface.poll_delay(&sockets, timestamp);
+        drop(iface_lock); // Let other processes use the network while we sleep
+        if let Some(wait_duration) = wait_duration {
+            let wait_duration: Duration = wait_duration.into();
+            syscall::sleep(wait_duration.as_secs_f64());
+        }
+    }
+}
+
+pub fn main(_args: &[&str]) -> usr::shell::ExitCode {
+    let prev_cidr = match *sys::net::IFACE.lock() {
+        Some(ref iface) => match iface.ip_addrs().first() {
+            Some(IpCidr::Ipv4(ip_addr)) => *ip_addr,
+            _ => Ipv4Cidr::new(Ipv4Address::UNSPECIFIED, 0),
+        },
+        None => return usr::shell::ExitCode::CommandError,
+    };
+
+    let mut sockets = SocketSet::new(vec![]);
+    let dhcp_rx_buffer = RawSocketBuffer::new([RawPacketMetadata::EMPTY; 1], vec![0; 900]);
+    let dhcp_tx_buffer = RawSocketBuffer::new([RawPacketMetadata::EMPTY; 1], vec![0; 600]);
 
-                let dns_servers: Vec<_> = config.dns_servers.iter().filter_map(|s| *s).map(|s| s.to_string()).collect();
-                if !dns_servers.is_empty() {
-                    println!("DNS: {}", dns_servers.join(", "));
+    let timestamp = sys::net::time();
+    let mut dhcp = Dhcpv4Client::new(&mut sockets, dhcp_rx_buffer, dhcp_tx_buffer, timestamp);
+
+    println!("DHCP Discover transmitted");
+    let timeout = 30.0;
+    let started = syscall::uptime();
+    loop {
+        if syscall::uptime() - started > timeout {
+            println!("Timeout reached");
+            return usr::shell::ExitCode::CommandError;
+        }
+        if sys::console::end_of_text() {
+            println!();
+            return usr::shell::ExitCode::CommandError;
+        }
+        let mut iface_lock = sys::net::IFACE.lock();
+        let mut iface = match iface_lock.as_mut() {
+            Some(iface) => iface,
+            None => return usr::shell::ExitCode::CommandError,
+        };
+        let timestamp = sys::net::time();
+        match iface.poll(&mut sockets, timestamp) {
+            Err(smoltcp::Error::Unrecognized) => {}
+            Err(e) => {
+                println!("Network Error: {}", e);
+            }
+            Ok(_) => {}
+        }
+        let res = dhcp.poll(&mut iface, &mut sockets, timestamp).unwrap_or_else(|e| {
+            println!("DHCP Error: {:?}", e);
+            None
+        });
+        if let Some(config) = res {
+            println!("DHCP Offer received");
+            if let Some(cidr) = config.address {
+                if cidr != prev_cidr {
+                    iface.update_ip_addrs(|addrs| {
+                        if let Some(addr) = addrs.iter_mut().next() {
+                            *addr = IpCidr::Ipv4(cidr);
+                        }
+                    });
+                    println!("Leased: {}", cidr);
                 }
+            }
 
-                return usr::shell::ExitCode::CommandSuccessful;
+            config.router.map(|router| {
+                iface.routes_mut().add_default_ipv4_route(router).unwrap()
+            });
+            iface.routes_mut().update(|routes_map| {
+                let unspecified = IpCidr::new(Ipv4Address::UNSPECIFIED.into(), 0);
+                if let Some(default_route) = routes_map.get(&unspecified) {
+                    println!("Router: {}", default_route.via_router);
+                }
+            });
+
+            let dns_servers: Vec<_> = config.dns_servers.iter().filter_map(|s| *s).map(|s| s.to_string()).collect();
+            if !dns_servers.is_empty() {
+                println!("DNS: {}", dns_servers.join(", "));
             }
 
-            if let Some(wait_duration) = iface.poll_delay(&sockets, timestamp) {
-                let wait_duration: Duration = wait_duration.into();
-                syscall::sleep(wait_duration.as_secs_f64());
+            let ip_addr = config.address.unwrap_or(prev_cidr).address();
+            drop(iface_lock);
+            let ntp_servers = request_ntp_servers(ip_addr).unwrap_or_default();
+            if !ntp_servers.is_empty() {
+                let servers: Vec<_> = ntp_servers.iter().map(|s| s.to_string()).collect();
+                println!("NTP: {}", servers.join(", "));
             }
+            *NTP_SERVERS.lock() = ntp_servers;
+
+            return usr::shell::ExitCode::CommandSuccessful;
+        }
+
+        let wait_duration = iface.poll_delay(&sockets, timestamp);
+        drop(iface_lock); // Let other processes use the network while we sleep
+        if let Some(wait_duration) = wait_duration {
+            let wait_duration: Duration = wait_duration.into();
+            syscall::sleep(wait_duration.as_secs_f64());
         }
     }
+}
+
+#[test_case]
+fn test_parse_ntp_servers() {
+    let xid = 0x1234_5678;
+    let ip_addr = Ipv4Address::new(10, 0, 2, 15);
+    let eth_addr = EthernetAddress([0x52, 0x54, 0x00, 0x12, 0x34, 0x56]);
+    let mut datagram = inform_request(xid, ip_addr, eth_addr);
+    assert_eq!(parse_ntp_servers(xid, &datagram), None); // Not a response
 
-    usr::shell::ExitCode::CommandError
+    datagram[0] = 2; // Boot reply
+    datagram[240..255].copy_from_slice(&[
+        OPTION_MESSAGE_TYPE, 1, MESSAGE_ACK,
+        OPTION_PAD,
+        OPTION_NTP_SERVERS, 8, 10, 0, 2, 2, 10, 0, 2, 3,
+        OPTION_END,
+    ]);
+    let servers = vec![Ipv4Address::new(10, 0, 2, 2), Ipv4Address::new(10, 0, 2, 3)];
+    assert_eq!(parse_ntp_servers(xid, &datagram), Some(servers));
+    assert_eq!(parse_ntp_servers(xid + 1, &datagram), None);
 }
@@ -1,6 +1,6 @@
 use crate::api::console::Style;
 use crate::api::fs;
-use crate::usr;
+use crate::{sys, usr};
 use object::{Object, ObjectSection};
 
 pub fn main(args: &[&str]) -> usr::shell::ExitCode {
@@ -16,6 +16,23 @@ pub fn main(args: &[&str]) -> usr::shell::ExitCode {
         let bin = buf.as_slice();
         if let Ok(obj) = object::File::parse(bin) {
             println!("ELF entry address: {:#x}", obj.entry());
+            match sys::process::parse_elf(bin) {
+                Ok((entry_point, segments)) => {
+                    println!();
+                    println!("{}Loaded segments{} (entry: {:#x})", color, reset, entry_point);
+                    for segment in segments {
+                        let r = "r";
+                        let w = if segment.is_writable() { "w" } else { "-" };
+                        let x = if segment.is_executable() { "x" } else { "-" };
+                        let bss = segment.size - segment.data.len() as u64;
+                        println!("{:#x} {}{}{} (size: {}, bss: {})", segment.addr, r, w, x, segment.size, bss);
+                    }
+                }
+                Err(err) => {
+                    println!();
+                    println!("Could not load ELF: {}", err);
+                }
+            }
             for section in obj.sections() {
                 if let Ok(name) = section.name() {
                     if name.is_empty() {
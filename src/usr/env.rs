@@ -1,4 +1,4 @@
-use crate::{sys, usr};
+use crate::{api, sys, usr};
 
 pub fn main(args: &[&str]) -> usr::shell::ExitCode {
     if args.len() == 1 {
@@ -10,7 +10,10 @@ pub fn main(args: &[&str]) -> usr::shell::ExitCode {
             if let Some(i) = arg.find('=') {
                 let (key, mut val) = arg.split_at(i);
                 val = &val[1..];
-                sys::process::set_env(key, val);
+                if api::process::set_env(key, val).is_err() {
+                    println!("Error: could not set '{}'", key);
+                    return usr::shell::ExitCode::CommandError;
+                }
                 println!("{}={}", key, val);
             } else {
                 println!("Error: could not parse '{}'", arg);
@@ -1,4 +1,4 @@
-use crate::{sys, usr};
+use crate::usr;
 use crate::api::fs;
 use crate::api::regex::Regex;
 use crate::api::console::Style;
@@ -24,7 +24,7 @@ impl PrintingState {
 
 // > find /tmp -name *.txt -line hello
 pub fn main(args: &[&str]) -> usr::shell::ExitCode {
-    let mut path: &str = &sys::process::dir();
+    let mut path: &str = &fs::current_dir();
     let mut name = None;
     let mut line = None;
     let mut i = 1;
@@ -67,9 +67,9 @@ pub fn main(args: &[&str]) -> usr::shell::ExitCode {
 }
 
 fn print_matching_lines(path: &str, pattern: &str, state: &mut PrintingState) {
-    if let Some(dir) = sys::fs::Dir::open(path) {
+    if let Ok(files) = fs::read_dir(path) {
         state.is_recursive = true;
-        for file in dir.entries() {
+        for file in files {
             let file_path = format!("{}/{}", path, file.name());
             if file.is_dir() {
                 print_matching_lines(&file_path, pattern, state);
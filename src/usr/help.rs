@@ -34,6 +34,7 @@ fn help_summary() -> usr::shell::ExitCode {
         ("e", "dit <file>",        "Edit existing or new file\n"),
         ("g", "oto <dir>",         "Go to directory\n"),
         ("h", "elp <command>",     "Display help about a command\n"),
+        ("k", "ill <pid>",         "Kill process\n"),
         ("l", "ist <dir>",         "List entries in directory\n"),
         ("m", "ove <file> <file>", "Move file from source to destination\n"),
         ("p", "rint <string>",     "Print string to screen\n"),
@@ -8,7 +8,6 @@ use core::convert::TryInto;
 use core::str;
 use core::time::Duration;
 use smoltcp::socket::{SocketSet, UdpPacketMetadata, UdpSocket, UdpSocketBuffer};
-use smoltcp::time::Instant;
 use smoltcp::wire::{IpAddress, IpEndpoint, Ipv4Address};
 
 // See RFC 1035 for implementation details
@@ -153,66 +152,70 @@ pub fn resolve(name: &str) -> Result<IpAddress, ResponseCode> {
             }
             _ => {}
         }
+    } else {
+        return Err(ResponseCode::NetworkError);
+    }
 
-        let timeout = 5.0;
-        let started = syscall::realtime();
-        loop {
-            if syscall::realtime() - started > timeout {
-                return Err(ResponseCode::NetworkError);
-            }
-            let timestamp = Instant::from_millis((syscall::realtime() * 1000.0) as i64);
-            match iface.poll(&mut sockets, timestamp) {
-                Err(smoltcp::Error::Unrecognized) => {}
-                Err(e) => {
-                    println!("Network Error: {}", e);
-                }
-                Ok(_) => {}
+    let timeout = 5.0;
+    let started = syscall::uptime();
+    loop {
+        if syscall::uptime() - started > timeout {
+            return Err(ResponseCode::NetworkError);
+        }
+        let mut iface_lock = sys::net::IFACE.lock();
+        let iface = iface_lock.as_mut().ok_or(ResponseCode::NetworkError)?;
+        let timestamp = sys::net::time();
+        match iface.poll(&mut sockets, timestamp) {
+            Err(smoltcp::Error::Unrecognized) => {}
+            Err(e) => {
+                println!("Network Error: {}", e);
             }
+            Ok(_) => {}
+        }
 
-            {
-                let mut socket = sockets.get::<UdpSocket>(udp_handle);
+        {
+            let mut socket = sockets.get::<UdpSocket>(udp_handle);
 
-                state = match state {
-                    State::Bind if !socket.is_open() => {
-                        socket.bind(client).unwrap();
-                        State::Query
-                    }
-                    State::Query if socket.can_send() => {
-                        socket.send_slice(&query.datagram, server).expect("cannot send");
-                        State::Response
-                    }
-                    State::Response if socket.can_recv() => {
-                        let (data, _) = socket.recv().expect("cannot receive");
-                        let message = Message::from(data);
-                        if message.id() == query.id() && message.is_response() {
-                            return match message.rcode() {
-                                ResponseCode::NoError => {
-                                    // TODO: Parse the datagram instead of
-                                    // extracting the last 4 bytes.
-                                    //let rdata = message.answer().rdata();
-                                    let n = message.datagram.len();
-                                    let rdata = &message.datagram[(n - 4)..];
-
-                                    Ok(IpAddress::from(Ipv4Address::from_bytes(rdata)))
-                                }
-                                rcode => {
-                                    Err(rcode)
-                                }
+            state = match state {
+                State::Bind if !socket.is_open() => {
+                    socket.bind(client).unwrap();
+                    State::Query
+                }
+                State::Query if socket.can_send() => {
+                    socket.send_slice(&query.datagram, server).expect("cannot send");
+                    State::Response
+                }
+                State::Response if socket.can_recv() => {
+                    let (data, _) = socket.recv().expect("cannot receive");
+                    let message = Message::from(data);
+                    if message.id() == query.id() && message.is_response() {
+                        return match message.rcode() {
+                            ResponseCode::NoError => {
+                                // TODO: Parse the datagram instead of
+                                // extracting the last 4 bytes.
+                                //let rdata = message.answer().rdata();
+                                let n = message.datagram.len();
+                                let rdata = &message.datagram[(n - 4)..];
+
+                                Ok(IpAddress::from(Ipv4Address::from_bytes(rdata)))
+                            }
+                            rcode => {
+                                Err(rcode)
                             }
                         }
-                        state
                     }
-                    _ => state
+                    state
                 }
+                _ => state
             }
+        }
 
-            if let Some(wait_duration) = iface.poll_delay(&sockets, timestamp) {
-                let wait_duration: Duration = wait_duration.into();
-                syscall::sleep(wait_duration.as_secs_f64());
-            }
+        let wait_duration = iface.poll_delay(&sockets, timestamp);
+        drop(iface_lock); // Let other processes use the network while we sleep
+        if let Some(wait_duration) = wait_duration {
+            let wait_duration: Duration = wait_duration.into();
+            syscall::sleep(wait_duration.as_secs_f64());
         }
-    } else {
-        Err(ResponseCode::NetworkError)
     }
 }
 
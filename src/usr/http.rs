@@ -8,7 +8,6 @@ use alloc::vec::Vec;
 use core::str::{self, FromStr};
 use core::time::Duration;
 use smoltcp::socket::{SocketSet, TcpSocket, TcpSocketBuffer};
-use smoltcp::time::Instant;
 use smoltcp::wire::IpAddress;
 
 #[derive(Debug)]
@@ -107,95 +106,102 @@ pub fn main(args: &[&str]) -> usr::shell::ExitCode {
             }
             _ => {}
         }
+    } else {
+        return usr::shell::ExitCode::CommandError;
+    }
 
-        let mut is_header = true;
-        let timeout = 5.0;
-        let started = syscall::realtime();
-        loop {
-            if syscall::realtime() - started > timeout {
-                println!("Timeout reached");
-                return usr::shell::ExitCode::CommandError;
-            }
-            if sys::console::end_of_text() {
-                println!();
-                return usr::shell::ExitCode::CommandError;
-            }
-            let timestamp = Instant::from_millis((syscall::realtime() * 1000.0) as i64);
-            match iface.poll(&mut sockets, timestamp) {
-                Err(smoltcp::Error::Unrecognized) => {}
-                Err(e) => {
-                    println!("Network Error: {}", e);
-                }
-                Ok(_) => {}
+    let mut is_header = true;
+    let timeout = 5.0;
+    let started = syscall::uptime();
+    loop {
+        if syscall::uptime() - started > timeout {
+            println!("Timeout reached");
+            return usr::shell::ExitCode::CommandError;
+        }
+        if sys::console::end_of_text() {
+            println!();
+            return usr::shell::ExitCode::CommandError;
+        }
+        let mut iface_lock = sys::net::IFACE.lock();
+        let iface = match iface_lock.as_mut() {
+            Some(iface) => iface,
+            None => return usr::shell::ExitCode::CommandError,
+        };
+        let timestamp = sys::net::time();
+        match iface.poll(&mut sockets, timestamp) {
+            Err(smoltcp::Error::Unrecognized) => {}
+            Err(e) => {
+                println!("Network Error: {}", e);
             }
+            Ok(_) => {}
+        }
 
-            {
-                let mut socket = sockets.get::<TcpSocket>(tcp_handle);
+        {
+            let mut socket = sockets.get::<TcpSocket>(tcp_handle);
 
-                state = match state {
-                    State::Connect if !socket.is_active() => {
-                        let local_port = 49152 + random::get_u16() % 16384;
-                        if is_verbose {
-                            println!("* Connecting to {}:{}", address, url.port);
-                        }
-                        if socket.connect((address, url.port), local_port).is_err() {
-                            println!("Could not connect to {}:{}", address, url.port);
-                            return usr::shell::ExitCode::CommandError;
-                        }
-                        State::Request
+            state = match state {
+                State::Connect if !socket.is_active() => {
+                    let local_port = 49152 + random::get_u16() % 16384;
+                    if is_verbose {
+                        println!("* Connecting to {}:{}", address, url.port);
                     }
-                    State::Request if socket.may_send() => {
-                        let http_get = "GET ".to_owned() + &url.path + " HTTP/1.1\r\n";
-                        let http_host = "Host: ".to_owned() + &url.host + "\r\n";
-                        let http_ua = "User-Agent: MOROS/".to_owned() + env!("CARGO_PKG_VERSION") + "\r\n";
-                        let http_connection = "Connection: close\r\n".to_owned();
-                        if is_verbose {
-                            print!("> {}", http_get);
-                            print!("> {}", http_host);
-                            print!("> {}", http_ua);
-                            print!("> {}", http_connection);
-                            println!(">");
-                        }
-                        socket.send_slice(http_get.as_ref()).expect("cannot send");
-                        socket.send_slice(http_host.as_ref()).expect("cannot send");
-                        socket.send_slice(http_ua.as_ref()).expect("cannot send");
-                        socket.send_slice(http_connection.as_ref()).expect("cannot send");
-                        socket.send_slice(b"\r\n").expect("cannot send");
-                        State::Response
+                    if socket.connect((address, url.port), local_port).is_err() {
+                        println!("Could not connect to {}:{}", address, url.port);
+                        return usr::shell::ExitCode::CommandError;
+                    }
+                    State::Request
+                }
+                State::Request if socket.may_send() => {
+                    let http_get = "GET ".to_owned() + &url.path + " HTTP/1.1\r\n";
+                    let http_host = "Host: ".to_owned() + &url.host + "\r\n";
+                    let http_ua = "User-Agent: MOROS/".to_owned() + env!("CARGO_PKG_VERSION") + "\r\n";
+                    let http_connection = "Connection: close\r\n".to_owned();
+                    if is_verbose {
+                        print!("> {}", http_get);
+                        print!("> {}", http_host);
+                        print!("> {}", http_ua);
+                        print!("> {}", http_connection);
+                        println!(">");
                     }
-                    State::Response if socket.can_recv() => {
-                        socket.recv(|data| {
-                            let contents = String::from_utf8_lossy(data);
-                            for line in contents.lines() {
-                                if is_header {
-                                    if line.is_empty() {
-                                        is_header = false;
-                                    }
-                                    if is_verbose {
-                                        println!("< {}", line);
-                                    }
-                                } else {
-                                    println!("{}", line);
+                    socket.send_slice(http_get.as_ref()).expect("cannot send");
+                    socket.send_slice(http_host.as_ref()).expect("cannot send");
+                    socket.send_slice(http_ua.as_ref()).expect("cannot send");
+                    socket.send_slice(http_connection.as_ref()).expect("cannot send");
+                    socket.send_slice(b"\r\n").expect("cannot send");
+                    State::Response
+                }
+                State::Response if socket.can_recv() => {
+                    socket.recv(|data| {
+                        let contents = String::from_utf8_lossy(data);
+                        for line in contents.lines() {
+                            if is_header {
+                                if line.is_empty() {
+                                    is_header = false;
                                 }
+                                if is_verbose {
+                                    println!("< {}", line);
+                                }
+                            } else {
+                                println!("{}", line);
                             }
-                            (data.len(), ())
-                        }).unwrap();
-                        State::Response
-                    }
-                    State::Response if !socket.may_recv() => {
-                        break;
-                    }
-                    _ => state
-                };
-            }
+                        }
+                        (data.len(), ())
+                    }).unwrap();
+                    State::Response
+                }
+                State::Response if !socket.may_recv() => {
+                    break;
+                }
+                _ => state
+            };
+        }
 
-            if let Some(wait_duration) = iface.poll_delay(&sockets, timestamp) {
-                let wait_duration: Duration = wait_duration.into();
-                syscall::sleep(wait_duration.as_secs_f64());
-            }
+        let wait_duration = iface.poll_delay(&sockets, timestamp);
+        drop(iface_lock); // Let other processes use the network while we sleep
+        if let Some(wait_duration) = wait_duration {
+            let wait_duration: Duration = wait_duration.into();
+            syscall::sleep(wait_duration.as_secs_f64());
         }
-        usr::shell::ExitCode::CommandSuccessful
-    } else {
-        usr::shell::ExitCode::CommandError
     }
+    usr::shell::ExitCode::CommandSuccessful
 }
@@ -43,20 +43,33 @@ pub fn main(_args: &[&str]) -> usr::shell::ExitCode {
 
         copy_file("/bin/hello", include_bytes!("../../dsk/bin/hello"));
         copy_file("/bin/sleep", include_bytes!("../../dsk/bin/sleep"));
+        copy_rust_programs();
 
         create_dir("/dev/clk"); // Clocks
         let pathname = "/dev/console";
-        if syscall::stat(pathname).is_none() {
+        if syscall::stat(pathname).is_err() {
             if fs::create_device(pathname, sys::fs::DeviceType::Console).is_some() {
                 println!("Created '{}'", pathname);
             }
         }
         let pathname = "/dev/random";
-        if syscall::stat(pathname).is_none() {
+        if syscall::stat(pathname).is_err() {
             if fs::create_device(pathname, sys::fs::DeviceType::Random).is_some() {
                 println!("Created '{}'", pathname);
             }
         }
+        let pathname = "/dev/log";
+        if syscall::stat(pathname).is_err() {
+            if fs::create_device(pathname, sys::fs::DeviceType::Log).is_some() {
+                println!("Created '{}'", pathname);
+            }
+        }
+        let pathname = "/dev/rtc";
+        if syscall::stat(pathname).is_err() {
+            if fs::create_device(pathname, sys::fs::DeviceType::RTC).is_some() {
+                println!("Created '{}'", pathname);
+            }
+        }
 
         copy_file("/ini/boot.sh", include_bytes!("../../dsk/ini/boot.sh"));
         copy_file("/ini/banner.txt", include_bytes!("../../dsk/ini/banner.txt"));
@@ -100,6 +113,17 @@ fn create_dir(pathname: &str) {
     }
 }
 
+// The Rust programs of `src/bin` are built by `build.rs` for the kernel
+#[cfg(not(feature = "userspace"))]
+fn copy_rust_programs() {
+    copy_file("/bin/args", include_bytes!(concat!(env!("OUT_DIR"), "/bin/args")));
+    copy_file("/bin/count", include_bytes!(concat!(env!("OUT_DIR"), "/bin/count")));
+    copy_file("/bin/heap", include_bytes!(concat!(env!("OUT_DIR"), "/bin/heap")));
+}
+
+#[cfg(feature = "userspace")]
+fn copy_rust_programs() {}
+
 fn copy_file(pathname: &str, buf: &[u8]) {
     if fs::exists(pathname) {
         return;
@@ -0,0 +1,17 @@
+use crate::{api, usr};
+
+pub fn main(args: &[&str]) -> usr::shell::ExitCode {
+    if args.len() != 2 {
+        println!("Usage: kill <pid>");
+        return usr::shell::ExitCode::CommandError;
+    }
+    if let Ok(id) = args[1].parse::<usize>() {
+        if api::process::kill(id).is_ok() {
+            return usr::shell::ExitCode::CommandSuccessful;
+        }
+        println!("Could not kill process {}", id);
+    } else {
+        println!("Could not parse pid '{}'", args[1]);
+    }
+    usr::shell::ExitCode::CommandError
+}
@@ -1,11 +1,11 @@
-use crate::{sys, usr};
+use crate::usr;
 use crate::api::console::Style;
+use crate::api::fs;
 use alloc::string::ToString;
-use alloc::vec::Vec;
 use time::OffsetDateTime;
 
 pub fn main(args: &[&str]) -> usr::shell::ExitCode {
-    let mut path: &str = &sys::process::dir();
+    let mut path: &str = &fs::current_dir();
     let mut sort = "name";
 
     let mut i = 1;
@@ -35,8 +35,7 @@ pub fn main(args: &[&str]) -> usr::shell::ExitCode {
         path = path.trim_end_matches('/');
     }
 
-    if let Some(dir) = sys::fs::Dir::open(path) {
-        let mut files: Vec<_> = dir.entries().collect();
+    if let Ok(mut files) = fs::read_dir(path) {
 
         match sort {
             "name" => files.sort_by_key(|f| f.name()),
@@ -0,0 +1,57 @@
+use crate::{api, usr};
+use crate::api::console::Style;
+use crate::api::syscall;
+use crate::sys::log::Level;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+
+pub fn main(args: &[&str]) -> usr::shell::ExitCode {
+    let max_level = match args.len() {
+        1 => Level::Debug,
+        2 => match args[1].parse() {
+            Ok(level) => level,
+            Err(_) => return usage(),
+        },
+        _ => return usage(),
+    };
+
+    let pathname = "/dev/log";
+    let handle = match api::fs::open_device(pathname) {
+        Some(handle) => handle,
+        None => {
+            println!("Could not open '{}'", pathname);
+            return usr::shell::ExitCode::CommandError;
+        }
+    };
+    let mut contents = Vec::new();
+    let mut buf = vec![0; 1024];
+    while let Ok(n) = syscall::read(handle, &mut buf) {
+        if n == 0 {
+            break;
+        }
+        contents.extend_from_slice(&buf[0..n]);
+    }
+    syscall::close(handle);
+
+    // The lines look like "[0.123456] WARN message"
+    let csi_reset = Style::reset();
+    for line in String::from_utf8_lossy(&contents).lines() {
+        let (time, rest) = line.split_once(' ').unwrap_or((line, ""));
+        let level = rest.split(' ').next().unwrap_or("");
+        match level.parse::<Level>() {
+            Ok(level) if level <= max_level => {
+                let csi_color = Style::color(level.color());
+                println!("{}{}{} {}", csi_color, time, csi_reset, rest);
+            }
+            Ok(_) => {}
+            Err(_) => println!("{}", line),
+        }
+    }
+    usr::shell::ExitCode::CommandSuccessful
+}
+
+fn usage() -> usr::shell::ExitCode {
+    println!("Usage: log [error|warning|info|debug]");
+    usr::shell::ExitCode::CommandError
+}
@@ -1,5 +1,6 @@
-use crate::{sys, usr};
+use crate::{api, sys, usr};
 use crate::api::console::Style;
+use alloc::format;
 use alloc::string::ToString;
 
 pub fn main(args: &[&str]) -> usr::shell::ExitCode {
@@ -17,15 +18,28 @@ pub fn main(args: &[&str]) -> usr::shell::ExitCode {
 }
 
 fn usage() -> usr::shell::ExitCode {
-    let size = sys::allocator::memory_size();
-    let used = sys::allocator::memory_used();
-    let free = size - used;
+    let heap = [sys::allocator::memory_size(), sys::allocator::memory_used(), sys::allocator::memory_free()];
+    let phys = [sys::mem::memory_size(), sys::mem::memory_used(), sys::mem::memory_free()];
 
-    let width = size.to_string().len();
+    let width = heap[0].max(phys[0]).to_string().len();
     let color = Style::color("LightCyan");
     let reset = Style::reset();
-    println!("{}Size:{} {:width$}", color, reset, size, width = width);
-    println!("{}Used:{} {:width$}", color, reset, used, width = width);
-    println!("{}Free:{} {:width$}", color, reset, free, width = width);
+    println!("{}Heap size:{} {:width$}", color, reset, heap[0], width = width);
+    println!("{}Heap used:{} {:width$}", color, reset, heap[1], width = width);
+    println!("{}Heap free:{} {:width$}", color, reset, heap[2], width = width);
+    println!("{}Phys size:{} {:width$}", color, reset, phys[0], width = width);
+    println!("{}Phys used:{} {:width$}", color, reset, phys[1], width = width);
+    println!("{}Phys free:{} {:width$}", color, reset, phys[2], width = width);
+
+    let procs = api::process::list();
+    if procs.len() > 1 {
+        println!();
+        println!("{}{:>4} {:>8} {:>8} NAME{}", color, "PID", "MEM", "HEAP", reset);
+        for proc in procs.iter().skip(1) { // The kernel has no user space
+            let memory = format!("{}K", proc.memory() >> 10);
+            let heap = format!("{}K", proc.heap() >> 10);
+            println!("{:>4} {:>8} {:>8} {}", proc.id(), memory, heap, proc.name());
+        }
+    }
     usr::shell::ExitCode::CommandSuccessful
 }
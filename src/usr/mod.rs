@@ -22,12 +22,16 @@ pub mod httpd;
 pub mod install;
 pub mod ip;
 pub mod keyboard;
+pub mod kill;
 pub mod list;
 pub mod lisp;
+pub mod log;
 pub mod mem;
 pub mod net;
+pub mod ntp;
 pub mod print;
 pub mod r#move;
+pub mod ps;
 pub mod read;
 pub mod route;
 pub mod shell;
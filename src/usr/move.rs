@@ -1,13 +1,40 @@
 use crate::usr;
+use crate::api::fs;
 
 pub fn main(args: &[&str]) -> usr::shell::ExitCode {
     if args.len() != 3 {
         return usr::shell::ExitCode::CommandError;
     }
 
-    // TODO: Avoid doing copy+delete
-    match usr::copy::main(args) {
-        usr::shell::ExitCode::CommandSuccessful => usr::delete::main(&args[0..2]),
-        _ => usr::shell::ExitCode::CommandError,
+    let source = args[1];
+    let dest = args[2];
+
+    for path in &[source, dest] {
+        if path.starts_with("/dev") || path.starts_with("/sys") {
+            println!("Permission denied to move '{}'", path);
+            return usr::shell::ExitCode::CommandError;
+        }
+    }
+
+    if !fs::exists(source) {
+        println!("File not found '{}'", source);
+        return usr::shell::ExitCode::CommandError;
+    }
+
+    if fs::realpath(source) == fs::realpath(dest) {
+        return usr::shell::ExitCode::CommandSuccessful;
+    }
+
+    // Replace the destination like a copy would do
+    if fs::exists(dest) && fs::delete(dest).is_err() {
+        println!("Could not replace '{}'", dest);
+        return usr::shell::ExitCode::CommandError;
+    }
+
+    if fs::rename(source, dest).is_ok() {
+        usr::shell::ExitCode::CommandSuccessful
+    } else {
+        println!("Could not move '{}' to '{}'", source, dest);
+        usr::shell::ExitCode::CommandError
     }
 }
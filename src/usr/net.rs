@@ -2,7 +2,6 @@ use crate::{sys, usr};
 use crate::api::syscall;
 //use smoltcp::wire::Ipv4Address;
 use smoltcp::socket::{SocketSet, TcpSocket, TcpSocketBuffer};
-use smoltcp::time::Instant;
 
 pub fn main(args: &[&str]) -> usr::shell::ExitCode {
     if args.len() == 1 {
@@ -10,52 +9,61 @@ pub fn main(args: &[&str]) -> usr::shell::ExitCode {
         return usr::shell::ExitCode::CommandError;
     }
 
-    if let Some(ref mut iface) = *sys::net::IFACE.lock() {
-        match args[1] {
-            "config" => {
-                if args.len() < 4 {
-                    println!("Usage: net config <key> <value>");
-                    return usr::shell::ExitCode::CommandError;
-                }
-                match args[2] {
-                    "debug" => {
-                        iface.device_mut().debug_mode = match args[3] {
-                            "1" | "on" | "enable" => true,
-                            "0" | "off" | "disable" => false,
-                            _ => {
-                                println!("Invalid config value");
-                                return usr::shell::ExitCode::CommandError;
-                            }
+    match args[1] {
+        "config" => {
+            if args.len() < 4 {
+                println!("Usage: net config <key> <value>");
+                return usr::shell::ExitCode::CommandError;
+            }
+            match args[2] {
+                "debug" => {
+                    let debug_mode = match args[3] {
+                        "1" | "on" | "enable" => true,
+                        "0" | "off" | "disable" => false,
+                        _ => {
+                            println!("Invalid config value");
+                            return usr::shell::ExitCode::CommandError;
                         }
+                    };
+                    if let Some(ref mut iface) = *sys::net::IFACE.lock() {
+                        iface.device_mut().debug_mode = debug_mode;
                     }
-                    _ => {
-                        println!("Invalid config key");
-                        return usr::shell::ExitCode::CommandError;
-                    }
+                }
+                _ => {
+                    println!("Invalid config key");
+                    return usr::shell::ExitCode::CommandError;
                 }
             }
-            "monitor" => {
+        }
+        "monitor" => {
+            if let Some(ref mut iface) = *sys::net::IFACE.lock() {
                 iface.device_mut().debug_mode = true;
+            } else {
+                return usr::shell::ExitCode::CommandSuccessful;
+            }
 
-                let mut server_rx_buffer = [0; 2048];
-                let mut server_tx_buffer = [0; 2048];
-                let server_socket = TcpSocket::new(
-                    TcpSocketBuffer::new(&mut server_rx_buffer[..]),
-                    TcpSocketBuffer::new(&mut server_tx_buffer[..]),
-                );
+            let mut server_rx_buffer = [0; 2048];
+            let mut server_tx_buffer = [0; 2048];
+            let server_socket = TcpSocket::new(
+                TcpSocketBuffer::new(&mut server_rx_buffer[..]),
+                TcpSocketBuffer::new(&mut server_tx_buffer[..]),
+            );
 
-                let mut sockets_storage = [None, None];
-                let mut sockets = SocketSet::new(&mut sockets_storage[..]);
-                let _server_handle = sockets.add(server_socket);
+            let mut sockets_storage = [None, None];
+            let mut sockets = SocketSet::new(&mut sockets_storage[..]);
+            let _server_handle = sockets.add(server_socket);
 
-                loop {
-                    if sys::console::end_of_text() {
-                        println!();
-                        return usr::shell::ExitCode::CommandSuccessful;
-                    }
+            loop {
+                if sys::console::end_of_text() {
+                    println!();
+                    return usr::shell::ExitCode::CommandSuccessful;
+                }
 
-                    let now = syscall::uptime();
-                    match iface.poll(&mut sockets, Instant::from_millis((now * 1000.0) as i64)) {
+                // The interface is unlocked while we sleep to let other
+                // processes use the network.
+                if let Some(ref mut iface) = *sys::net::IFACE.lock() {
+                    let timestamp = sys::net::time();
+                    match iface.poll(&mut sockets, timestamp) {
                         Ok(true) => {
                             //println!("{}", "-".repeat(66));
                             //println!("Polling result: Ok(true)");
@@ -69,13 +77,13 @@ pub fn main(args: &[&str]) -> usr::shell::ExitCode {
                             //println!("polling result: err({})", e);
                         }
                     }
-                    syscall::sleep(0.1);
                 }
+                syscall::sleep(0.1);
             }
-            _ => {
-                println!("Invalid command");
-                return usr::shell::ExitCode::CommandError;
-            }
+        }
+        _ => {
+            println!("Invalid command");
+            return usr::shell::ExitCode::CommandError;
         }
     }
     usr::shell::ExitCode::CommandSuccessful
@@ -0,0 +1,301 @@
+use crate::{api, sys, usr};
+use crate::api::syscall;
+use crate::api::random;
+use alloc::string::String;
+use alloc::vec;
+use core::convert::TryInto;
+use core::str::FromStr;
+use core::time::Duration;
+use smoltcp::socket::{SocketSet, UdpPacketMetadata, UdpSocket, UdpSocketBuffer};
+use smoltcp::wire::{IpAddress, IpEndpoint};
+use time::OffsetDateTime;
+
+// See RFC 4330 for implementation details
+
+const NTP_PORT: u16 = 123;
+const PACKET_SIZE: usize = 48;
+
+// Seconds between the NTP epoch (1900) and the Unix epoch (1970)
+const NTP_UNIX_DELTA: f64 = 2_208_988_800.0;
+
+const VERSION: u8 = 4;
+const MODE_CLIENT: u8 = 3;
+const MODE_SERVER: u8 = 4;
+
+struct Packet {
+    pub datagram: [u8; PACKET_SIZE],
+}
+
+impl Packet {
+    pub fn request(transmit_timestamp: u64) -> Self {
+        let mut datagram = [0; PACKET_SIZE];
+        datagram[0] = (VERSION << 3) | MODE_CLIENT; // Leap indicator 0
+        datagram[40..48].copy_from_slice(&transmit_timestamp.to_be_bytes());
+        Self { datagram }
+    }
+
+    pub fn from(data: &[u8]) -> Option<Self> {
+        let datagram = data.get(0..PACKET_SIZE)?.try_into().ok()?;
+        Some(Self { datagram })
+    }
+
+    pub fn leap_indicator(&self) -> u8 {
+        self.datagram[0] >> 6
+    }
+
+    pub fn mode(&self) -> u8 {
+        self.datagram[0] & 0b111
+    }
+
+    // A stratum of 0 is a "kiss-of-death" telling the client to go away
+    pub fn stratum(&self) -> u8 {
+        self.datagram[1]
+    }
+
+    fn timestamp(&self, i: usize) -> u64 {
+        u64::from_be_bytes(self.datagram[i..(i + 8)].try_into().unwrap())
+    }
+
+    pub fn originate_timestamp(&self) -> u64 {
+        self.timestamp(24)
+    }
+
+    pub fn receive_timestamp(&self) -> u64 {
+        self.timestamp(32)
+    }
+
+    pub fn transmit_timestamp(&self) -> u64 {
+        self.timestamp(40)
+    }
+
+    pub fn is_valid_response(&self, request: &Packet) -> bool {
+        self.mode() == MODE_SERVER
+            && self.leap_indicator() != 3 // Clock not synchronized
+            && self.stratum() != 0
+            && self.originate_timestamp() == request.transmit_timestamp()
+            && self.transmit_timestamp() != 0
+    }
+}
+
+// NTP timestamps are made of 32 bits of seconds since 1900 followed by
+// 32 bits of fraction of second.
+fn to_ntp_timestamp(seconds: f64) -> u64 {
+    let seconds = seconds + NTP_UNIX_DELTA;
+    let fract = seconds - libm::floor(seconds);
+    ((seconds as u64) << 32) | ((fract * 4_294_967_296.0) as u64 & 0xFFFF_FFFF)
+}
+
+fn from_ntp_timestamp(timestamp: u64) -> f64 {
+    let seconds = (timestamp >> 32) as f64;
+    let fract = (timestamp & 0xFFFF_FFFF) as f64 / 4_294_967_296.0;
+    seconds + fract - NTP_UNIX_DELTA
+}
+
+#[derive(Debug)]
+pub struct Measure {
+    pub offset: f64, // Seconds to add to the local clock
+    pub delay: f64, // Round trip delay in seconds
+}
+
+// Compute the clock offset and the round trip delay from the originate (t1),
+// receive (t2), transmit (t3), and destination (t4) timestamps.
+fn measure(t1: f64, t2: f64, t3: f64, t4: f64) -> Measure {
+    Measure {
+        offset: ((t2 - t1) + (t3 - t4)) / 2.0,
+        delay: (t4 - t1) - (t3 - t2),
+    }
+}
+
+pub fn query(server: IpAddress) -> Result<Measure, String> {
+    let server = IpEndpoint::new(server, NTP_PORT);
+
+    let local_port = 49152 + random::get_u16() % 16384;
+    let client = IpEndpoint::new(IpAddress::Unspecified, local_port);
+
+    let udp_rx_buffer = UdpSocketBuffer::new(vec![UdpPacketMetadata::EMPTY], vec![0; 512]);
+    let udp_tx_buffer = UdpSocketBuffer::new(vec![UdpPacketMetadata::EMPTY], vec![0; 512]);
+    let udp_socket = UdpSocket::new(udp_rx_buffer, udp_tx_buffer);
+
+    let mut sockets = SocketSet::new(vec![]);
+    let udp_handle = sockets.add(udp_socket);
+
+    enum State { Bind, Request, Response }
+    let mut state = State::Bind;
+    let mut request = Packet::request(0);
+    let mut t1 = 0.0;
+    if let Some(ref mut iface) = *sys::net::IFACE.lock() {
+        match iface.ipv4_addr() {
+            None => {
+                return Err("Interface not ready".into());
+            }
+            Some(ip_addr) if ip_addr.is_unspecified() => {
+                return Err("Interface not ready".into());
+            }
+            _ => {}
+        }
+    } else {
+        return Err("Interface not found".into());
+    }
+
+    let timeout = 5.0;
+    let started = syscall::uptime();
+    loop {
+        if syscall::uptime() - started > timeout {
+            return Err("Timeout reached".into());
+        }
+        if sys::console::end_of_text() {
+            return Err("Interrupted".into());
+        }
+        let mut iface_lock = sys::net::IFACE.lock();
+        let iface = match iface_lock.as_mut() {
+            Some(iface) => iface,
+            None => return Err("Interface not found".into()),
+        };
+        let timestamp = sys::net::time();
+        match iface.poll(&mut sockets, timestamp) {
+            Err(smoltcp::Error::Unrecognized) => {}
+            Err(e) => {
+                println!("Network Error: {}", e);
+            }
+            Ok(_) => {}
+        }
+
+        {
+            let mut socket = sockets.get::<UdpSocket>(udp_handle);
+
+            state = match state {
+                State::Bind if !socket.is_open() => {
+                    if socket.bind(client).is_err() {
+                        return Err("Could not bind socket".into());
+                    }
+                    State::Request
+                }
+                State::Request if socket.can_send() => {
+                    t1 = syscall::realtime();
+                    request = Packet::request(to_ntp_timestamp(t1));
+                    if socket.send_slice(&request.datagram, server).is_err() {
+                        return Err("Could not send request".into());
+                    }
+                    State::Response
+                }
+                State::Response if socket.can_recv() => {
+                    let (data, _) = match socket.recv() {
+                        Ok(res) => res,
+                        Err(_) => return Err("Could not receive response".into()),
+                    };
+                    let t4 = syscall::realtime();
+                    match Packet::from(data) {
+                        Some(response) if response.is_valid_response(&request) => {
+                            let t2 = from_ntp_timestamp(response.receive_timestamp());
+                            let t3 = from_ntp_timestamp(response.transmit_timestamp());
+                            return Ok(measure(t1, t2, t3, t4));
+                        }
+                        _ => state
+                    }
+                }
+                _ => state
+            }
+        }
+
+        let wait_duration = iface.poll_delay(&sockets, timestamp);
+        drop(iface_lock); // Let other processes use the network while we sleep
+        if let Some(wait_duration) = wait_duration {
+            let wait_duration: Duration = wait_duration.into();
+            syscall::sleep(wait_duration.as_secs_f64());
+        }
+    }
+}
+
+// Write the realtime clock to the RTC at the beginning of a second, because
+// the RTC doesn't keep the fraction of second.
+fn write_rtc() -> Result<(), ()> {
+    let seconds = syscall::realtime();
+    syscall::sleep(libm::ceil(seconds) - seconds);
+    let seconds = libm::round(syscall::realtime());
+    let date = OffsetDateTime::from_unix_timestamp(seconds as i64); // UTC
+    let handle = api::fs::open_device("/dev/rtc").ok_or(())?;
+    let res = syscall::write(handle, date.format("%FT%H:%M:%S").as_bytes());
+    syscall::close(handle);
+    res.map(|_| ()).map_err(|_| ())
+}
+
+fn usage() -> usr::shell::ExitCode {
+    println!("Usage: ntp [<server>] [--rtc]");
+    usr::shell::ExitCode::CommandError
+}
+
+pub fn main(args: &[&str]) -> usr::shell::ExitCode {
+    let mut host = None;
+    let mut set_rtc = false;
+    for &arg in &args[1..] {
+        match arg {
+            "--rtc" | "-r" => set_rtc = true,
+            _ if arg.starts_with('-') => return usage(),
+            _ if host.is_none() => host = Some(arg),
+            _ => return usage(),
+        }
+    }
+
+    let address = match host {
+        Some(host) if host.ends_with(char::is_numeric) => {
+            match IpAddress::from_str(host) {
+                Ok(ip_addr) => ip_addr,
+                Err(_) => {
+                    println!("Invalid address '{}'", host);
+                    return usr::shell::ExitCode::CommandError;
+                }
+            }
+        }
+        Some(host) => {
+            match usr::host::resolve(host) {
+                Ok(ip_addr) => ip_addr,
+                Err(e) => {
+                    println!("Could not resolve host: {:?}", e);
+                    return usr::shell::ExitCode::CommandError;
+                }
+            }
+        }
+        None => {
+            match usr::dhcp::ntp_servers().first() {
+                Some(ip_addr) => IpAddress::from(*ip_addr),
+                None => {
+                    println!("Could not find a server, run `dhcp` or give one");
+                    return usr::shell::ExitCode::CommandError;
+                }
+            }
+        }
+    };
+
+    match query(address) {
+        Ok(measure) => {
+            syscall::adjtime(measure.offset);
+            println!("Server: {}", address);
+            println!("Offset: {:+.6} s", measure.offset);
+            println!("Delay:  {:.6} s", measure.delay);
+            if set_rtc {
+                if write_rtc().is_err() {
+                    println!("Could not write to '/dev/rtc'");
+                    return usr::shell::ExitCode::CommandError;
+                }
+                println!("RTC updated");
+            }
+            usr::shell::ExitCode::CommandSuccessful
+        }
+        Err(e) => {
+            println!("Error: {}", e);
+            usr::shell::ExitCode::CommandError
+        }
+    }
+}
+
+#[test_case]
+fn test_ntp_measure() {
+    let t = 1629734102.5;
+    assert_eq!(from_ntp_timestamp(to_ntp_timestamp(t)), t);
+    assert_eq!(to_ntp_timestamp(0.0), 2_208_988_800 << 32);
+
+    // The server is 10 seconds ahead with 100 ms of network delay each way
+    let m = measure(100.0, 110.1, 110.2, 100.3);
+    assert!(libm::fabs(m.offset - 10.0) < 1e-9);
+    assert!(libm::fabs(m.delay - 0.2) < 1e-9);
+}
@@ -0,0 +1,21 @@
+use crate::{api, usr};
+use crate::api::console::Style;
+use crate::sys::process::ProcessState;
+use alloc::format;
+
+pub fn main(_args: &[&str]) -> usr::shell::ExitCode {
+    let color = Style::color("LightCyan");
+    let reset = Style::reset();
+    println!("{}{:>4} {:>4} {:8} {:8} {:>8} NAME{}", color, "PID", "PPID", "USER", "STATE", "MEM", reset);
+    for proc in api::process::list() {
+        let state = match proc.state() {
+            ProcessState::Ready => "ready",
+            ProcessState::Running => "running",
+            ProcessState::Exited => "exited",
+        };
+        let user = if proc.user().is_empty() { "-" } else { proc.user() };
+        let memory = format!("{}K", proc.memory() >> 10);
+        println!("{:>4} {:>4} {:8} {:8} {:>8} {}", proc.id(), proc.parent_id(), user, state, memory, proc.name());
+    }
+    usr::shell::ExitCode::CommandSuccessful
+}
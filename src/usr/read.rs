@@ -14,14 +14,12 @@ pub fn main(args: &[&str]) -> usr::shell::ExitCode {
 
     match pathname {
         "/dev/rtc" => {
-            let rtc = CMOS::new().rtc();
-            println!(
-                "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
-                rtc.year, rtc.month, rtc.day,
-                rtc.hour, rtc.minute, rtc.second
-            );
+            println!("{}", CMOS::new().rtc());
             usr::shell::ExitCode::CommandSuccessful
         },
+        "/dev/log" => {
+            usr::log::main(&["log"])
+        },
         "/dev/clk/realtime" => {
             println!("{:.6}", syscall::realtime());
             usr::shell::ExitCode::CommandSuccessful
@@ -63,7 +61,7 @@ pub fn main(args: &[&str]) -> usr::shell::ExitCode {
                         }
                     }
                 }
-            } else if let Some(stat) = syscall::stat(pathname) {
+            } else if let Ok(stat) = syscall::stat(pathname) {
                 if stat.is_file() {
                     if let Ok(contents) = api::fs::read_to_string(pathname) {
                         print!("{}", contents);
@@ -7,11 +7,11 @@ use alloc::vec::Vec;
 use alloc::string::String;
 
 // TODO: Scan /bin
-const AUTOCOMPLETE_COMMANDS: [&str; 36] = [
+const AUTOCOMPLETE_COMMANDS: [&str; 40] = [
     "base64", "clear", "colors", "copy", "date", "delete", "dhcp", "disk", "edit", "env", "exit",
     "geotime", "goto", "halt", "help", "hex", "host", "http", "httpd", "install", "ip", "keyboard",
-    "lisp", "list", "memory", "move", "net", "print", "read", "route", "shell", "sleep", "tcp",
-    "user", "vga", "write"
+    "kill", "lisp", "list", "log", "memory", "move", "net", "ntp", "print", "ps", "read", "route",
+    "shell", "sleep", "tcp", "user", "vga", "write"
 ];
 
 #[repr(u8)]
@@ -39,8 +39,8 @@ fn shell_completer(line: &str) -> Vec<String> {
         let dirname = fs::dirname(&pathname);
         let filename = fs::filename(&pathname);
         let sep = if dirname.ends_with('/') { "" } else { "/" };
-        if let Some(dir) = sys::fs::Dir::open(dirname) {
-            for entry in dir.entries() {
+        if let Ok(files) = fs::read_dir(dirname) {
+            for entry in files {
                 let name = entry.name();
                 if name.starts_with(filename) {
                     let end = if entry.is_dir() { "/" } else { "" };
@@ -101,7 +101,7 @@ pub fn split_args(cmd: &str) -> Vec<&str> {
 fn change_dir(args: &[&str]) -> ExitCode {
     match args.len() {
         1 => {
-            println!("{}", sys::process::dir());
+            println!("{}", fs::current_dir());
             ExitCode::CommandSuccessful
         },
         2 => {
@@ -109,8 +109,7 @@ fn change_dir(args: &[&str]) -> ExitCode {
             if pathname.len() > 1 {
                 pathname = pathname.trim_end_matches('/').into();
             }
-            if sys::fs::Dir::open(&pathname).is_some() {
-                sys::process::set_dir(&pathname);
+            if fs::change_dir(&pathname).is_ok() {
                 ExitCode::CommandSuccessful
             } else {
                 println!("File not found '{}'", pathname);
@@ -138,7 +137,7 @@ pub fn exec(cmd: &str) -> ExitCode {
         "h" | "help"           => usr::help::main(&args),
         "i"                    => ExitCode::CommandUnknown,
         "j" | "jump"           => ExitCode::CommandUnknown,
-        "k" | "kill"           => ExitCode::CommandUnknown,
+        "k" | "kill"           => usr::kill::main(&args),
         "l" | "list"           => usr::list::main(&args),
         "m" | "move"           => usr::r#move::main(&args),
         "n"                    => ExitCode::CommandUnknown,
@@ -170,6 +169,7 @@ pub fn exec(cmd: &str) -> ExitCode {
         "httpd"                => usr::httpd::main(&args),
         "tcp"                  => usr::tcp::main(&args),
         "host"                 => usr::host::main(&args),
+        "ntp"                  => usr::ntp::main(&args),
         "install"              => usr::install::main(&args),
         "ip"                   => usr::ip::main(&args),
         "geotime"              => usr::geotime::main(&args),
@@ -177,16 +177,20 @@ pub fn exec(cmd: &str) -> ExitCode {
         "dsk" | "disk"         => usr::disk::main(&args),
         "user"                 => usr::user::main(&args),
         "mem" | "memory"       => usr::mem::main(&args),
+        "log"                  => usr::log::main(&args),
         "kb" | "keyboard"      => usr::keyboard::main(&args),
         "lisp"                 => usr::lisp::main(&args),
         "chess"                => usr::chess::main(&args),
         "beep"                 => usr::beep::main(&args),
         "elf"                  => usr::elf::main(&args),
+        "ps"                   => usr::ps::main(&args),
         cmd                    => {
-            if api::process::spawn(cmd).is_ok() {
-                ExitCode::CommandSuccessful
-            } else {
-                ExitCode::CommandUnknown
+            match api::process::spawn(cmd, &args) {
+                Ok(id) => match api::process::wait(id) {
+                    Ok(0) => ExitCode::CommandSuccessful,
+                    _ => ExitCode::CommandError,
+                },
+                Err(_) => ExitCode::CommandUnknown,
             }
         }
     }